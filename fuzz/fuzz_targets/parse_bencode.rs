@@ -0,0 +1,42 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use torrent::bencode::{
+    decode_bencode, decode_bencode_strict, encode_bencode, parse_bencode, DecodeLimits,
+    DecodeStatus, StreamDecoder, MAX_PARSE_DEPTH,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let parsed = parse_bencode(data);
+    if let Err(e) = &parsed {
+        assert!(e.offset() <= data.len());
+    }
+
+    if let Ok(value) = decode_bencode(data) {
+        // re-encoding gives the canonical form, which must decode to the same value
+        let canonical = encode_bencode(&value);
+        assert_eq!(decode_bencode_strict(&canonical).as_ref(), Ok(&value));
+        if decode_bencode_strict(data).is_ok() {
+            assert_eq!(canonical, data);
+        }
+    }
+
+    // the stream decoder has to agree with the parser whatever the chunking
+    let mut decoder = StreamDecoder::new(DecodeLimits {
+        max_depth: MAX_PARSE_DEPTH,
+        max_string_len: usize::MAX,
+        max_total_len: usize::MAX,
+    });
+    let mut streamed = Ok(DecodeStatus::NeedMore);
+    for chunk in data.chunks(7) {
+        streamed = decoder.push(chunk);
+        if streamed != Ok(DecodeStatus::NeedMore) {
+            break;
+        }
+    }
+    match (streamed, parsed) {
+        (Ok(DecodeStatus::Done(value)), Ok(parsed)) => assert_eq!(value, parsed.data),
+        (Ok(DecodeStatus::NeedMore), Err(_)) | (Err(_), Err(_)) => {}
+        (streamed, parsed) => panic!("stream {:?} but parse {:?}", streamed, parsed),
+    }
+});
@@ -0,0 +1,364 @@
+use crate::bencode::{decode_bencode_ref, BDictRef, BencodeRef, SerdeError};
+use serde::de::{
+    DeserializeSeed, Deserializer, EnumAccess, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use serde::{forward_to_deserialize_any, Deserialize};
+use std::collections::btree_map;
+
+/// Decodes `bytes` as exactly one bencoded value and deserializes it into `T`.
+///
+/// Strings borrow from `bytes`, so `&[u8]` and `&str` fields work without copying.
+pub fn from_bytes<'de, T: Deserialize<'de>>(bytes: &'de [u8]) -> Result<T, SerdeError> {
+    let value = decode_bencode_ref(bytes)?;
+    T::deserialize(ValueDeserializer { value: &value })
+}
+
+/// Deserializes `T` from an already parsed value.
+pub fn from_bencode_ref<'de, T: Deserialize<'de>>(value: &BencodeRef<'de>) -> Result<T, SerdeError> {
+    T::deserialize(ValueDeserializer { value })
+}
+
+struct ValueDeserializer<'a, 'de> {
+    value: &'a BencodeRef<'de>,
+}
+
+impl<'a, 'de> ValueDeserializer<'a, 'de> {
+    fn int(&self) -> Result<i128, SerdeError> {
+        match self.value {
+            BencodeRef::Int(int_val) => Ok(*int_val),
+            _ => Err(SerdeError::Message(String::from("expected integer"))),
+        }
+    }
+}
+
+impl<'a, 'de> Deserializer<'de> for ValueDeserializer<'a, 'de> {
+    type Error = SerdeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            BencodeRef::Str(str_val) => match std::str::from_utf8(str_val) {
+                Ok(as_str) => visitor.visit_borrowed_str(as_str),
+                Err(_) => visitor.visit_borrowed_bytes(str_val),
+            },
+            BencodeRef::Int(int_val) => {
+                if let Ok(int_val) = i64::try_from(*int_val) {
+                    visitor.visit_i64(int_val)
+                } else if let Ok(int_val) = u64::try_from(*int_val) {
+                    visitor.visit_u64(int_val)
+                } else {
+                    visitor.visit_i128(*int_val)
+                }
+            }
+            BencodeRef::List(list_val) => visitor.visit_seq(ListAccess { iter: list_val.iter() }),
+            BencodeRef::Dict(dict_val) => visitor.visit_map(DictAccess::new(dict_val)),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.int()? {
+            0 => visitor.visit_bool(false),
+            1 => visitor.visit_bool(true),
+            _ => Err(SerdeError::Message(String::from("expected 0 or 1 for bool"))),
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            BencodeRef::Str(str_val) => visitor.visit_borrowed_bytes(str_val),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    // bencode has no null, a present value is always `Some`
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.value {
+            BencodeRef::Str(_) => visitor.visit_enum(VariantDeserializer { variant: self.value, value: None }),
+            BencodeRef::Dict(dict_val) if dict_val.entries.len() == 1 => {
+                let (variant, value) = dict_val.entries.iter().next().unwrap();
+                visitor.visit_enum(VariantDeserializer {
+                    variant: &BencodeRef::Str(variant),
+                    value: Some(value),
+                })
+            }
+            _ => Err(SerdeError::Message(String::from(
+                "expected string or single key dictionary for enum",
+            ))),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct ListAccess<'a, 'de> {
+    iter: std::slice::Iter<'a, BencodeRef<'de>>,
+}
+
+impl<'a, 'de> SeqAccess<'de> for ListAccess<'a, 'de> {
+    type Error = SerdeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        match self.iter.next() {
+            Some(value) => seed.deserialize(ValueDeserializer { value }).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct DictAccess<'a, 'de> {
+    iter: btree_map::Iter<'a, &'de [u8], BencodeRef<'de>>,
+    value: Option<&'a BencodeRef<'de>>,
+}
+
+impl<'a, 'de> DictAccess<'a, 'de> {
+    fn new(dict: &'a BDictRef<'de>) -> Self {
+        DictAccess {
+            iter: dict.entries.iter(),
+            value: None,
+        }
+    }
+}
+
+impl<'a, 'de> MapAccess<'de> for DictAccess<'a, 'de> {
+    type Error = SerdeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(ValueDeserializer { value: &BencodeRef::Str(key) })
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Self::Error> {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| SerdeError::Message(String::from("value requested before key")))?;
+        seed.deserialize(ValueDeserializer { value })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+// Unit variants are plain strings, every other variant is `{name: value}`.
+struct VariantDeserializer<'a, 'de> {
+    variant: &'a BencodeRef<'de>,
+    value: Option<&'a BencodeRef<'de>>,
+}
+
+impl<'a, 'de> EnumAccess<'de> for VariantDeserializer<'a, 'de> {
+    type Error = SerdeError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), Self::Error> {
+        let variant = seed.deserialize(ValueDeserializer { value: self.variant })?;
+        Ok((variant, self))
+    }
+}
+
+impl<'a, 'de> VariantAccess<'de> for VariantDeserializer<'a, 'de> {
+    type Error = SerdeError;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        match self.value {
+            None => Ok(()),
+            Some(_) => Err(SerdeError::Message(String::from("expected unit variant"))),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Self::Error> {
+        match self.value {
+            Some(value) => seed.deserialize(ValueDeserializer { value }),
+            None => Err(SerdeError::Message(String::from("expected newtype variant"))),
+        }
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Some(value) => ValueDeserializer { value }.deserialize_seq(visitor),
+            None => Err(SerdeError::Message(String::from("expected tuple variant"))),
+        }
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Some(value) => ValueDeserializer { value }.deserialize_map(visitor),
+            None => Err(SerdeError::Message(String::from("expected struct variant"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod serde_tests {
+    use super::*;
+    use crate::bencode::to_bytes;
+    use serde::Serialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct File {
+        length: u64,
+        path: Vec<String>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Info {
+        name: String,
+        #[serde(rename = "piece length")]
+        piece_length: u64,
+        #[serde(with = "serde_bytes")]
+        pieces: Vec<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        private: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        files: Option<Vec<File>>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Torrent {
+        announce: Option<String>,
+        info: Info,
+        #[serde(flatten)]
+        extra: BTreeMap<String, String>,
+    }
+
+    #[test]
+    fn test_round_trip_struct() {
+        let input: &[u8] = b"d8:announce3:url7:comment2:hi4:infod5:filesld6:lengthi3e4:pathl1:a1:beee4:name1:n12:piece lengthi16384e6:pieces2:\xff\x00ee";
+        let torrent: Torrent = from_bytes(input).unwrap();
+        assert_eq!(
+            torrent,
+            Torrent {
+                announce: Some(String::from("url")),
+                info: Info {
+                    name: String::from("n"),
+                    piece_length: 16384,
+                    pieces: vec![0xff, 0x00],
+                    private: None,
+                    files: Some(vec![File {
+                        length: 3,
+                        path: vec![String::from("a"), String::from("b")],
+                    }]),
+                },
+                extra: BTreeMap::from([(String::from("comment"), String::from("hi"))]),
+            }
+        );
+        assert_eq!(to_bytes(&torrent).unwrap(), input);
+    }
+
+    #[test]
+    fn test_optional_fields() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Response {
+            interval: i32,
+            #[serde(rename = "tracker id")]
+            tracker_id: Option<String>,
+        }
+        let response: Response = from_bytes(b"d8:intervali1800ee").unwrap();
+        assert_eq!(response, Response { interval: 1800, tracker_id: None });
+        // `None` is left out rather than written as an empty value
+        assert_eq!(to_bytes(&response).unwrap(), b"d8:intervali1800ee");
+    }
+
+    #[test]
+    fn test_borrowed_bytes() {
+        #[derive(Deserialize)]
+        struct Message<'a> {
+            #[serde(borrow, with = "serde_bytes")]
+            data: &'a [u8],
+        }
+        let input = b"d4:data3:\x01\x02\x03e";
+        let message: Message = from_bytes(input).unwrap();
+        assert_eq!(message.data, [1, 2, 3]);
+        assert_eq!(message.data.as_ptr(), input[9..].as_ptr());
+    }
+
+    #[test]
+    fn test_enums() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum Event {
+            Started,
+            Piece(u32),
+            Range { start: u32, end: u32 },
+        }
+        for event in [Event::Started, Event::Piece(3), Event::Range { start: 1, end: 2 }] {
+            let encoded = to_bytes(&event).unwrap();
+            assert_eq!(from_bytes::<Event>(&encoded).unwrap(), event);
+        }
+        assert_eq!(to_bytes(&Event::Started).unwrap(), b"7:Started");
+        assert_eq!(to_bytes(&Event::Piece(3)).unwrap(), b"d5:Piecei3ee");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            from_bytes::<u8>(b"i256e"),
+            Err(SerdeError::Message(String::from(
+                "invalid value: integer `256`, expected u8"
+            )))
+        );
+        assert_eq!(
+            from_bytes::<u8>(b"i1"),
+            Err(SerdeError::Bencode(crate::bencode::BencodeError::UnexpectedEof { offset: 2 }))
+        );
+        assert!(matches!(from_bytes::<File>(b"d6:lengthi1ee"), Err(SerdeError::Message(_))));
+        assert_eq!(to_bytes(&1.5f64), Err(SerdeError::Unsupported("f64")));
+        assert_eq!(to_bytes(&vec![Some(1), None]), Err(SerdeError::Unsupported("none inside a list")));
+    }
+}
@@ -0,0 +1,207 @@
+use crate::bencode::{write_bencode, write_int, write_string, BInt, BString, Bencode};
+use std::io::{self, Write};
+
+enum Frame {
+    List,
+    Dict {
+        last_key: Option<BString>,
+        expect_key: bool,
+    },
+}
+
+/// Event based writer for producing bencode without building a [`Bencode`] tree.
+///
+/// Dictionary keys must be written in strictly increasing byte order so the
+/// output is always canonical. Misuse, such as a value where a key is
+/// expected, fails with `io::ErrorKind::InvalidInput` and nothing is written.
+pub struct Encoder<W: Write> {
+    writer: W,
+    stack: Vec<Frame>,
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(writer: W) -> Self {
+        Encoder {
+            writer,
+            stack: Vec::new(),
+        }
+    }
+
+    pub fn begin_list(&mut self) -> io::Result<()> {
+        self.before_value()?;
+        self.writer.write_all(b"l")?;
+        self.stack.push(Frame::List);
+        Ok(())
+    }
+
+    pub fn begin_dict(&mut self) -> io::Result<()> {
+        self.before_value()?;
+        self.writer.write_all(b"d")?;
+        self.stack.push(Frame::Dict {
+            last_key: None,
+            expect_key: true,
+        });
+        Ok(())
+    }
+
+    /// Writes the next dictionary key. Must be greater than the previous key.
+    pub fn key(&mut self, key: &[u8]) -> io::Result<()> {
+        match self.stack.last_mut() {
+            Some(Frame::Dict {
+                last_key,
+                expect_key,
+            }) if *expect_key => {
+                if let Some(last_key) = last_key {
+                    if key <= last_key.as_slice() {
+                        return Err(invalid_input("dictionary keys must be unique and sorted"));
+                    }
+                }
+                write_string(key, &mut self.writer)?;
+                *last_key = Some(key.to_vec());
+                *expect_key = false;
+                Ok(())
+            }
+            _ => Err(invalid_input("key written outside of a dictionary key position")),
+        }
+    }
+
+    pub fn int(&mut self, int: BInt) -> io::Result<()> {
+        self.before_value()?;
+        write_int(int, &mut self.writer)
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.before_value()?;
+        write_string(bytes, &mut self.writer)
+    }
+
+    pub fn str(&mut self, string: &str) -> io::Result<()> {
+        self.bytes(string.as_bytes())
+    }
+
+    /// Writes a whole value at the current position.
+    pub fn value(&mut self, value: &Bencode) -> io::Result<()> {
+        self.before_value()?;
+        write_bencode(value, &mut self.writer)
+    }
+
+    /// Writes an already encoded value verbatim, such as an info dictionary
+    /// that must keep its original bytes. The bytes are not checked.
+    pub fn raw(&mut self, encoded: &[u8]) -> io::Result<()> {
+        self.before_value()?;
+        self.writer.write_all(encoded)
+    }
+
+    /// Closes the innermost open list or dictionary.
+    pub fn end(&mut self) -> io::Result<()> {
+        match self.stack.last() {
+            Some(Frame::Dict {
+                expect_key: false, ..
+            }) => Err(invalid_input("dictionary key without a value")),
+            Some(_) => {
+                self.writer.write_all(b"e")?;
+                self.stack.pop();
+                Ok(())
+            }
+            None => Err(invalid_input("end without an open list or dictionary")),
+        }
+    }
+
+    /// Flushes and returns the writer. Fails if a list or dictionary is still open.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.stack.is_empty() {
+            return Err(invalid_input("unclosed list or dictionary"));
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    // Checks that a value may be written here and records it in the parent.
+    fn before_value(&mut self) -> io::Result<()> {
+        if let Some(Frame::Dict { expect_key, .. }) = self.stack.last_mut() {
+            if *expect_key {
+                return Err(invalid_input("dictionary value written without a key"));
+            }
+            *expect_key = true;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod encoder_tests {
+    use super::*;
+    use crate::bencode::{decode_bencode, encode_bencode, encoded_len};
+
+    #[test]
+    fn test_events() {
+        let mut encoder = Encoder::new(Vec::new());
+        encoder.begin_dict().unwrap();
+        encoder.key(b"info").unwrap();
+        encoder.begin_dict().unwrap();
+        encoder.key(b"length").unwrap();
+        encoder.int(-12).unwrap();
+        encoder.key(b"pieces").unwrap();
+        encoder.bytes(&[0, 1, 2]).unwrap();
+        encoder.end().unwrap();
+        encoder.key(b"kept").unwrap();
+        encoder.raw(b"d1:bi1e1:ai2ee").unwrap();
+        encoder.key(b"list").unwrap();
+        encoder.begin_list().unwrap();
+        encoder.str("a").unwrap();
+        encoder.value(&Bencode::List(vec![Bencode::Int(1)])).unwrap();
+        encoder.end().unwrap();
+        encoder.end().unwrap();
+        assert_eq!(
+            encoder.finish().unwrap(),
+            b"d4:infod6:lengthi-12e6:pieces3:\x00\x01\x02e4:keptd1:bi1e1:ai2ee4:listl1:ali1eeee"
+        );
+    }
+
+    #[test]
+    fn test_misuse() {
+        let mut encoder = Encoder::new(Vec::new());
+        assert!(encoder.key(b"a").is_err());
+        assert!(encoder.end().is_err());
+        encoder.begin_dict().unwrap();
+        assert!(encoder.int(1).is_err());
+        encoder.key(b"b").unwrap();
+        assert!(encoder.key(b"c").is_err());
+        assert!(encoder.end().is_err());
+        encoder.int(1).unwrap();
+        assert!(encoder.key(b"b").is_err());
+        assert!(encoder.key(b"a").is_err());
+        encoder.key(b"c").unwrap();
+        encoder.int(2).unwrap();
+        assert_eq!(encoder.writer, b"d1:bi1e1:ci2e");
+        let encoder_result = encoder.finish();
+        assert_eq!(
+            encoder_result.map_err(|e| e.kind()).unwrap_err(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_write_and_len_match_encode() {
+        let inputs: [&[u8]; 6] = [
+            b"i0e",
+            b"i-1234567890e",
+            b"0:",
+            b"le",
+            b"d4:listli12e3:zln6:whatupd1:k1:vee4:mdhe4:here3:numi-234ee",
+            b"l10:0123456789i170141183460469231731687303715884105727ee",
+        ];
+        for input in inputs {
+            let value = decode_bencode(input).unwrap();
+            let mut written = Vec::new();
+            write_bencode(&value, &mut written).unwrap();
+            assert_eq!(written, input);
+            assert_eq!(encode_bencode(&value), input);
+            assert_eq!(encoded_len(&value), input.len());
+        }
+    }
+}
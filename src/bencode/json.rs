@@ -0,0 +1,151 @@
+use crate::bencode::{BDict, BString, Bencode};
+use data_encoding::{BASE64, HEXLOWER_PERMISSIVE};
+use serde_json::{Map, Number, Value};
+use std::fmt::{Display, Formatter};
+
+// Byte strings that are not valid utf8 are written as a JSON string with one
+// of these prefixes. Text that happens to start with a prefix is written the
+// same way, so every JSON string decodes back to exactly one byte string.
+const HEX_PREFIX: &str = "hex:";
+const BASE64_PREFIX: &str = "base64:";
+
+/// How binary byte strings are written in JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryEncoding {
+    Hex,
+    Base64,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum JsonError {
+    /// JSON type with no bencode counterpart: null, bool or a fractional number.
+    UnsupportedValue(String),
+    /// A `hex:` or `base64:` string whose payload does not decode.
+    InvalidBinary(String),
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonError::UnsupportedValue(value) => write!(f, "{} cannot be represented in bencode", value),
+            JsonError::InvalidBinary(value) => write!(f, "invalid binary string {}", value),
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+fn string_to_json(string: &[u8], binary: BinaryEncoding) -> String {
+    match std::str::from_utf8(string) {
+        Ok(text) if !text.starts_with(HEX_PREFIX) && !text.starts_with(BASE64_PREFIX) => {
+            text.to_string()
+        }
+        _ => match binary {
+            BinaryEncoding::Hex => format!("{}{}", HEX_PREFIX, HEXLOWER_PERMISSIVE.encode(string)),
+            BinaryEncoding::Base64 => format!("{}{}", BASE64_PREFIX, BASE64.encode(string)),
+        },
+    }
+}
+
+fn string_from_json(string: &str) -> Result<BString, JsonError> {
+    let decoded = if let Some(hex) = string.strip_prefix(HEX_PREFIX) {
+        HEXLOWER_PERMISSIVE.decode(hex.as_bytes())
+    } else if let Some(base64) = string.strip_prefix(BASE64_PREFIX) {
+        BASE64.decode(base64.as_bytes())
+    } else {
+        return Ok(string.as_bytes().to_vec());
+    };
+    decoded.map_err(|_| JsonError::InvalidBinary(string.to_string()))
+}
+
+/// Converts a value to JSON: integers become numbers, lists arrays and
+/// dictionaries objects. Text strings are kept as is and binary strings are
+/// prefixed with `hex:` or `base64:`.
+pub fn to_json(value: &Bencode, binary: BinaryEncoding) -> Value {
+    match value {
+        Bencode::Str(str_val) => Value::String(string_to_json(str_val, binary)),
+        // Arbitrary precision numbers keep integers outside the i64 range intact.
+        Bencode::Int(int_val) => Value::Number(
+            int_val
+                .to_string()
+                .parse::<Number>()
+                .expect("an integer is always a valid JSON number"),
+        ),
+        Bencode::List(list_val) => {
+            Value::Array(list_val.iter().map(|value| to_json(value, binary)).collect())
+        }
+        Bencode::Dict(dict_val) => Value::Object(
+            dict_val
+                .iter()
+                .map(|(key, value)| (string_to_json(key, binary), to_json(value, binary)))
+                .collect::<Map<String, Value>>(),
+        ),
+    }
+}
+
+/// Reverse of [`to_json`]. Either binary encoding is accepted.
+pub fn from_json(value: &Value) -> Result<Bencode, JsonError> {
+    match value {
+        Value::String(string) => Ok(Bencode::Str(string_from_json(string)?)),
+        Value::Number(number) => number
+            .to_string()
+            .parse()
+            .map(Bencode::Int)
+            .map_err(|_| JsonError::UnsupportedValue(number.to_string())),
+        Value::Array(array) => Ok(Bencode::List(array.iter().map(from_json).collect::<Result<_, _>>()?)),
+        Value::Object(object) => {
+            let mut dict = BDict::new();
+            for (key, value) in object {
+                dict.insert(string_from_json(key)?, from_json(value)?);
+            }
+            Ok(Bencode::Dict(dict))
+        }
+        Value::Null | Value::Bool(_) => Err(JsonError::UnsupportedValue(value.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod json_tests {
+    use super::*;
+    use crate::bencode::{decode_bencode, encode_bencode};
+    use serde_json::json;
+
+    #[test]
+    fn test_to_json() {
+        let value = decode_bencode(b"d4:name4:test6:pieces3:\x00\xff\x014:sizei-12ee").unwrap();
+        assert_eq!(
+            to_json(&value, BinaryEncoding::Hex),
+            json!({"name": "test", "pieces": "hex:00ff01", "size": -12})
+        );
+        assert_eq!(
+            to_json(&value, BinaryEncoding::Base64),
+            json!({"name": "test", "pieces": "base64:AP8B", "size": -12})
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let input: &[u8] = b"d2:\xfe\xffl4:texti170141183460469231731687303715884105727e6:hex:abee";
+        let value = decode_bencode(input).unwrap();
+        for binary in [BinaryEncoding::Hex, BinaryEncoding::Base64] {
+            let json = to_json(&value, binary);
+            let text = serde_json::to_string_pretty(&json).unwrap();
+            let parsed: Value = serde_json::from_str(&text).unwrap();
+            assert_eq!(encode_bencode(&from_json(&parsed).unwrap()), input);
+        }
+        // text that looks like a marker is escaped rather than misread
+        assert_eq!(
+            to_json(&value, BinaryEncoding::Hex),
+            json!({"hex:feff": ["text", 170141183460469231731687303715884105727i128, "hex:6865783a6162"]})
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(from_json(&json!(null)), Err(JsonError::UnsupportedValue(String::from("null"))));
+        assert_eq!(from_json(&json!([true])), Err(JsonError::UnsupportedValue(String::from("true"))));
+        assert_eq!(from_json(&json!(1.5)), Err(JsonError::UnsupportedValue(String::from("1.5"))));
+        assert_eq!(from_json(&json!("hex:zz")), Err(JsonError::InvalidBinary(String::from("hex:zz"))));
+        assert_eq!(from_json(&json!({"base64:!": 1})), Err(JsonError::InvalidBinary(String::from("base64:!"))));
+    }
+}
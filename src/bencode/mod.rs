@@ -1,12 +1,31 @@
 //https://www.bittorrent.org/beps/bep_0003.html
-use std::collections::HashMap;
-use std::fmt::{Debug, Formatter};
-use crate::str_utils::{sub_arr, sub_str, vec_index_of};
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::io::{self, Write};
+use std::ops::Range;
+
+mod de;
+mod encoder;
+pub mod json;
+mod path;
+#[cfg(test)]
+mod prop_tests;
+mod ser;
+mod stream;
+
+pub use de::{from_bencode_ref, from_bytes};
+pub use encoder::Encoder;
+pub use path::PathError;
+pub use ser::{to_bencode, to_bytes};
+pub use stream::{DecodeLimits, DecodeStatus, StreamDecoder};
 
 pub type BString = Vec<u8>;
 pub type BInt = i128;
-pub type BDict = HashMap<String, Bencode>;
+/// Keys are raw byte strings kept in sorted order, which is also the order
+/// BEP 3 requires when encoding.
+pub type BDict = BTreeMap<BString, Bencode>;
 pub type BList = Vec<Bencode>;
+pub type BListRef<'a> = Vec<BencodeRef<'a>>;
 
 #[derive(PartialEq,Clone)]
 pub enum Bencode {
@@ -14,11 +33,10 @@ pub enum Bencode {
     Int(BInt),
     List(BList),
     Dict(BDict),
-    End,
 }
 
 impl Bencode {
-    fn new_str(string: impl Into<String>) -> Self {
+    pub fn new_str(string: impl Into<String>) -> Self {
         Bencode::Str(string.into().as_bytes().to_vec())
     }
 }
@@ -41,22 +59,207 @@ impl Debug for Bencode {
                 Ok(write!(f, "{:#?}", list_val)?)
             }
             Bencode::Dict(dict_val) => {
-                Ok(write!(f, "{:#?}", dict_val)?)
-            }
-            Bencode::End => {
-                Ok(write!(f, "END")?)
+                let mut map = f.debug_map();
+                for (key, value) in dict_val {
+                    map.entry(&String::from_utf8_lossy(key), value);
+                }
+                map.finish()
             }
         }
     }
 }
 
+/// A parsed value borrowing its strings and dictionary keys from the input.
+///
+/// Parsing into this form never copies string data; convert with
+/// `Bencode::from(&value)` when an owned tree is needed.
+#[derive(PartialEq, Clone, Debug)]
+pub enum BencodeRef<'a> {
+    Str(&'a [u8]),
+    Int(BInt),
+    List(BListRef<'a>),
+    Dict(BDictRef<'a>),
+}
+
+/// A borrowed dictionary together with the exact bytes it was parsed from.
+///
+/// `raw` is what the info-hash must be computed over: re-encoding `entries`
+/// loses duplicate keys, key order and any non-canonical formatting.
+#[derive(PartialEq, Clone, Debug)]
+pub struct BDictRef<'a> {
+    pub entries: BTreeMap<&'a [u8], BencodeRef<'a>>,
+    pub raw: &'a [u8],
+    /// Position of `raw` within the parsed input.
+    pub span: Range<usize>,
+}
+
+impl<'a> BDictRef<'a> {
+    pub fn get<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> Option<&BencodeRef<'a>> {
+        self.entries.get(key.as_ref())
+    }
+}
+
+impl From<&BencodeRef<'_>> for Bencode {
+    fn from(value: &BencodeRef<'_>) -> Self {
+        match value {
+            BencodeRef::Str(str_val) => Bencode::Str(str_val.to_vec()),
+            BencodeRef::Int(int_val) => Bencode::Int(*int_val),
+            BencodeRef::List(list_val) => Bencode::List(list_val.iter().map(Bencode::from).collect()),
+            BencodeRef::Dict(dict_val) => Bencode::Dict(
+                dict_val
+                    .entries
+                    .iter()
+                    .map(|(key, value)| (key.to_vec(), Bencode::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
 
 enum BencodeTypes {
     Str,
     Int,
     List,
     Dict,
-    End,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BencodeError {
+    /// Input ended before the value was complete.
+    UnexpectedEof { offset: usize },
+    /// A byte that cannot start a value (including a stray `e`).
+    UnexpectedByte { offset: usize, byte: u8 },
+    /// String length prefix is missing, malformed or runs past the input.
+    InvalidLength { offset: usize },
+    /// Integer is empty, not decimal or does not fit in a `BInt`.
+    InvalidInteger { offset: usize },
+    /// Integer or string length written with a leading zero, e.g. `i03e`.
+    LeadingZero { offset: usize },
+    /// `i-0e` is not a valid integer.
+    NegativeZero { offset: usize },
+    /// Dictionary key that is not a byte string.
+    NonStringKey { offset: usize },
+    /// Strict mode: dictionary key that sorts before the previous key.
+    UnsortedKey { offset: usize },
+    /// Strict mode: dictionary key that appears more than once.
+    DuplicateKey { offset: usize },
+    /// Bytes left over after a complete top level value.
+    TrailingData { offset: usize },
+    /// Lists and dictionaries nested deeper than the decoder allows.
+    DepthLimitExceeded { offset: usize },
+    /// String longer than the decoder allows.
+    StringTooLong { offset: usize },
+    /// Value larger than the decoder allows.
+    SizeLimitExceeded { offset: usize },
+}
+
+impl BencodeError {
+    pub fn offset(&self) -> usize {
+        match self {
+            BencodeError::UnexpectedEof { offset }
+            | BencodeError::UnexpectedByte { offset, .. }
+            | BencodeError::InvalidLength { offset }
+            | BencodeError::InvalidInteger { offset }
+            | BencodeError::LeadingZero { offset }
+            | BencodeError::NegativeZero { offset }
+            | BencodeError::NonStringKey { offset }
+            | BencodeError::UnsortedKey { offset }
+            | BencodeError::DuplicateKey { offset }
+            | BencodeError::TrailingData { offset }
+            | BencodeError::DepthLimitExceeded { offset }
+            | BencodeError::StringTooLong { offset }
+            | BencodeError::SizeLimitExceeded { offset } => *offset,
+        }
+    }
+}
+
+impl Display for BencodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BencodeError::UnexpectedEof { offset } => {
+                write!(f, "unexpected end of input at byte {}", offset)
+            }
+            BencodeError::UnexpectedByte { offset, byte } => {
+                write!(f, "unexpected byte 0x{:02x} at byte {}", byte, offset)
+            }
+            BencodeError::InvalidLength { offset } => {
+                write!(f, "invalid string length at byte {}", offset)
+            }
+            BencodeError::InvalidInteger { offset } => {
+                write!(f, "invalid integer at byte {}", offset)
+            }
+            BencodeError::LeadingZero { offset } => {
+                write!(f, "leading zero at byte {}", offset)
+            }
+            BencodeError::NegativeZero { offset } => {
+                write!(f, "negative zero at byte {}", offset)
+            }
+            BencodeError::NonStringKey { offset } => {
+                write!(f, "dictionary key is not a string at byte {}", offset)
+            }
+            BencodeError::UnsortedKey { offset } => {
+                write!(f, "dictionary key out of order at byte {}", offset)
+            }
+            BencodeError::DuplicateKey { offset } => {
+                write!(f, "duplicate dictionary key at byte {}", offset)
+            }
+            BencodeError::TrailingData { offset } => {
+                write!(f, "trailing data at byte {}", offset)
+            }
+            BencodeError::DepthLimitExceeded { offset } => {
+                write!(f, "nesting too deep at byte {}", offset)
+            }
+            BencodeError::StringTooLong { offset } => {
+                write!(f, "string too long at byte {}", offset)
+            }
+            BencodeError::SizeLimitExceeded { offset } => {
+                write!(f, "value too large at byte {}", offset)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BencodeError {}
+
+/// Error returned by [`from_bytes`] and [`to_bytes`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SerdeError {
+    /// The input was not valid bencode.
+    Bencode(BencodeError),
+    /// The value has no bencode representation, e.g. floats.
+    Unsupported(&'static str),
+    /// Raised by a `Serialize`/`Deserialize` impl, such as a missing field.
+    Message(String),
+}
+
+impl From<BencodeError> for SerdeError {
+    fn from(value: BencodeError) -> Self {
+        SerdeError::Bencode(value)
+    }
+}
+
+impl Display for SerdeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SerdeError::Bencode(e) => write!(f, "{}", e),
+            SerdeError::Unsupported(what) => write!(f, "{} cannot be represented in bencode", what),
+            SerdeError::Message(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for SerdeError {}
+
+impl serde::ser::Error for SerdeError {
+    fn custom<T: Display>(msg: T) -> Self {
+        SerdeError::Message(msg.to_string())
+    }
+}
+
+impl serde::de::Error for SerdeError {
+    fn custom<T: Display>(msg: T) -> Self {
+        SerdeError::Message(msg.to_string())
+    }
 }
 
 #[derive(Debug)]
@@ -75,211 +278,322 @@ impl<T> ParseResult<T> {
     }
 }
 
-pub fn parse_bencode(line: &[u8]) -> Result<ParseResult<Bencode>, ()>
+/// Parses a single value from the start of `line`, ignoring anything after it.
+pub fn parse_bencode(line: &[u8]) -> Result<ParseResult<Bencode>, BencodeError>
 {
-    let ben_type = get_type(line);
+    let ParseResult { data, len } = parse_bencode_ref(line)?;
+    Ok(ParseResult::new(Bencode::from(&data), len))
+}
+
+/// Parses `line` as exactly one value, rejecting trailing bytes.
+pub fn decode_bencode(line: &[u8]) -> Result<Bencode, BencodeError> {
+    Ok(Bencode::from(&decode_bencode_ref(line)?))
+}
+
+/// Like [`decode_bencode`], but also rejects dictionaries whose keys are not
+/// unique and sorted, as BEP 3 requires.
+pub fn decode_bencode_strict(line: &[u8]) -> Result<Bencode, BencodeError> {
+    Ok(Bencode::from(&decode_bencode_ref_strict(line)?))
+}
+
+/// Borrowing counterpart of [`parse_bencode`]: strings and keys point into `line`.
+pub fn parse_bencode_ref(line: &[u8]) -> Result<ParseResult<BencodeRef<'_>>, BencodeError> {
+    parse_value(line, 0, false, 0)
+}
+
+/// Borrowing counterpart of [`decode_bencode`].
+pub fn decode_bencode_ref(line: &[u8]) -> Result<BencodeRef<'_>, BencodeError> {
+    decode_whole(line, false)
+}
+
+/// Borrowing counterpart of [`decode_bencode_strict`].
+pub fn decode_bencode_ref_strict(line: &[u8]) -> Result<BencodeRef<'_>, BencodeError> {
+    decode_whole(line, true)
+}
+
+fn decode_whole(line: &[u8], strict: bool) -> Result<BencodeRef<'_>, BencodeError> {
+    let ParseResult { data, len } = parse_value(line, 0, strict, 0)?;
+    if len != line.len() {
+        return Err(BencodeError::TrailingData { offset: len });
+    }
+    Ok(data)
+}
+
+// All parse functions take the whole input and the position to start at, and
+// return the number of bytes consumed from there. Errors carry absolute offsets.
+// `strict` enforces canonical key order in dictionaries, `depth` counts the
+// lists and dictionaries around `pos`.
+fn parse_value(
+    line: &[u8],
+    pos: usize,
+    strict: bool,
+    depth: usize,
+) -> Result<ParseResult<BencodeRef<'_>>, BencodeError> {
+    let ben_type = get_type(line, pos)?;
+    if matches!(ben_type, BencodeTypes::List | BencodeTypes::Dict) && depth >= MAX_PARSE_DEPTH {
+        return Err(BencodeError::DepthLimitExceeded { offset: pos });
+    }
     match ben_type {
         BencodeTypes::Str => {
-            let res = parse_string(line);
-            if let Ok(res) = res {
-                let ParseResult { data, len } = res;
-                Ok(ParseResult::new(Bencode::Str(data), len))
-            } else {
-                panic!("Invalid String {:?}", line.to_vec())
-            }
+            let ParseResult { data, len } = parse_string(line, pos)?;
+            Ok(ParseResult::new(BencodeRef::Str(data), len))
         }
         BencodeTypes::Int => {
-            let res = parse_int(line);
-            if let Ok(res) = res {
-                let ParseResult { data, len } = res;
-                Ok(ParseResult::new(Bencode::Int(data), len))
-            } else {
-                Err(())
-            }
+            let ParseResult { data, len } = parse_int(line, pos)?;
+            Ok(ParseResult::new(BencodeRef::Int(data), len))
         }
         BencodeTypes::List => {
-            let res = parse_list(line);
-            if let Ok(res) = res {
-                let ParseResult { data, len } = res;
-                Ok(ParseResult::new(Bencode::List(data), len))
-            } else {
-                Err(())
-            }
+            let ParseResult { data, len } = parse_list(line, pos, strict, depth + 1)?;
+            Ok(ParseResult::new(BencodeRef::List(data), len))
         }
         BencodeTypes::Dict => {
-            let res = parse_dict(line);
-            if let Ok(res) = res {
-                let ParseResult { data, len } = res;
-                Ok(ParseResult::new(Bencode::Dict(data), len))
-            } else {
-                Err(())
-            }
+            let ParseResult { data, len } = parse_dict(line, pos, strict, depth + 1)?;
+            Ok(ParseResult::new(BencodeRef::Dict(data), len))
+        }
+    }
+}
+
+fn get_type(line: &[u8], pos: usize) -> Result<BencodeTypes, BencodeError> {
+    match line.get(pos) {
+        Some(b'i') => Ok(BencodeTypes::Int),
+        Some(b'd') => Ok(BencodeTypes::Dict),
+        Some(b'l') => Ok(BencodeTypes::List),
+        Some(b'0'..=b'9') => Ok(BencodeTypes::Str),
+        Some(byte) => Err(BencodeError::UnexpectedByte { offset: pos, byte: *byte }),
+        None => Err(BencodeError::UnexpectedEof { offset: pos }),
+    }
+}
+
+// Past these many characters an integer or string length is certain to
+// overflow, so parsing stops there instead of reading on to the terminator.
+// A BInt has at most 39 digits plus a sign.
+const MAX_INT_CHARS: usize = 41;
+const MAX_LENGTH_DIGITS: usize = 20;
+
+// Nesting the parser accepts before giving up rather than risking the stack.
+// Real torrents stay in single digits, even v2 file trees.
+pub const MAX_PARSE_DEPTH: usize = 256;
+
+// Checks the digits of an integer or string length for the forms BEP 3
+// forbids. `digits` must not include the sign.
+fn check_digits(digits: &[u8], negative: bool, offset: usize) -> Result<(), BencodeError> {
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return Err(BencodeError::InvalidInteger { offset });
+    }
+    if digits[0] == b'0' {
+        if digits.len() > 1 {
+            return Err(BencodeError::LeadingZero { offset });
         }
-        BencodeTypes::End => {
-            Ok(ParseResult::new(Bencode::End, 1))
+        if negative {
+            return Err(BencodeError::NegativeZero { offset });
         }
     }
+    Ok(())
 }
 
-fn get_type(line: &[u8]) -> BencodeTypes {
-    // let line = line.into();
-    let first_char = sub_arr(line.to_vec(), 0, 1);
-    match String::from_utf8(first_char).unwrap().as_str() {
-        "i" => BencodeTypes::Int,
-        "d" => BencodeTypes::Dict,
-        "l" => BencodeTypes::List,
-        "e" => BencodeTypes::End,
-        "" => BencodeTypes::End,
-        _ => BencodeTypes::Str,
-    }
-}
-fn parse_string(line: &[u8]) -> Result<ParseResult<BString>, ()> {
-    // let line = line.into();
-    // let separator_idx = index_of(&line, ':');
-    let separator_idx = vec_index_of(&line.to_vec(), ":".as_bytes()[0]);
-    match separator_idx {
-        Ok(separator_idx) => {
-            let x = sub_arr(line.to_vec(), 0, separator_idx);
-            let len = sub_str(String::from_utf8(x.clone()).unwrap_or_else(|_| { panic!("String length needs to be valid utf8 {:?}", x) }), 0, separator_idx);
-            let len = len.parse::<usize>().unwrap_or_else(|_| { panic!("Invalid string") });
-            // let string = sub_str(&line, separator_idx + 1, len);
-            let string = sub_arr(line.to_vec(), separator_idx + 1, len);
-            Ok(ParseResult::new(string, separator_idx + 1 + len))
+fn parse_string(line: &[u8], pos: usize) -> Result<ParseResult<&[u8]>, BencodeError> {
+    let rest = &line[pos..];
+    let mut separator_idx = 0;
+    loop {
+        match rest.get(separator_idx) {
+            None => return Err(BencodeError::UnexpectedEof { offset: line.len() }),
+            Some(b':') => break,
+            Some(byte) if !byte.is_ascii_digit() || separator_idx > MAX_LENGTH_DIGITS => {
+                return Err(BencodeError::InvalidLength { offset: pos })
+            }
+            Some(_) => separator_idx += 1,
         }
-        Err(_) => {
-            Err(())
+    }
+    let len_digits = &rest[..separator_idx];
+    check_digits(len_digits, false, pos).map_err(|e| match e {
+        BencodeError::LeadingZero { .. } => e,
+        _ => BencodeError::InvalidLength { offset: pos },
+    })?;
+    // digits are checked above, so the only possible failure is overflow
+    let len = std::str::from_utf8(len_digits)
+        .ok()
+        .and_then(|len| len.parse::<usize>().ok())
+        .ok_or(BencodeError::InvalidLength { offset: pos })?;
+    let start = separator_idx + 1;
+    if rest.len() - start < len {
+        return Err(BencodeError::UnexpectedEof { offset: line.len() });
+    }
+    Ok(ParseResult::new(&rest[start..start + len], start + len))
+}
+fn parse_int(line: &[u8], pos: usize) -> Result<ParseResult<BInt>, BencodeError> {
+    let rest = &line[pos..];
+    if rest.first() != Some(&b'i') {
+        return Err(BencodeError::InvalidInteger { offset: pos });
+    }
+    let mut index_of_end = 1;
+    loop {
+        match rest.get(index_of_end) {
+            None => return Err(BencodeError::UnexpectedEof { offset: line.len() }),
+            Some(b'e') => break,
+            Some(b'-') if index_of_end == 1 => index_of_end += 1,
+            Some(byte) if !byte.is_ascii_digit() || index_of_end > MAX_INT_CHARS => {
+                return Err(BencodeError::InvalidInteger { offset: pos + 1 })
+            }
+            Some(_) => index_of_end += 1,
         }
     }
+    let num_bytes = &rest[1..index_of_end];
+    let (negative, digits) = match num_bytes.first() {
+        Some(b'-') => (true, &num_bytes[1..]),
+        _ => (false, num_bytes),
+    };
+    check_digits(digits, negative, pos + 1)?;
+    let num = std::str::from_utf8(num_bytes)
+        .ok()
+        .and_then(|num| num.parse::<BInt>().ok())
+        .ok_or(BencodeError::InvalidInteger { offset: pos + 1 })?;
+    Ok(ParseResult::new(num, index_of_end + 1))
 }
-fn parse_int(line: &[u8]) -> Result<ParseResult<BInt>, ()> {
-    let first_char = sub_arr(line.to_vec(), 0, 1)[0];
-    if first_char == "i".as_bytes()[0] {
-        let index_of_end = vec_index_of(&line.to_vec(), "e".as_bytes()[0]);
-        if let Ok(index_of_end) = index_of_end {
-            let x = sub_arr(line.to_vec(), 1, index_of_end - 1);
-            let num = sub_str(String::from_utf8(x.clone()).unwrap_or_else(|_| { panic!("Integer needs to be valid utf8 {:?}", x) }), 0, index_of_end - 1).parse().unwrap_or_else(|_| { panic!("Invalid Integer") });
-            return Ok(ParseResult::new(num, index_of_end + 1));
+
+fn parse_list(
+    line: &[u8],
+    pos: usize,
+    strict: bool,
+    depth: usize,
+) -> Result<ParseResult<BListRef<'_>>, BencodeError> {
+    if line.get(pos) != Some(&b'l') {
+        return Err(BencodeError::UnexpectedByte { offset: pos, byte: line.get(pos).copied().unwrap_or(0) });
+    }
+    let mut ret_vec = Vec::new();
+    let mut total_parsed = 1;
+    loop {
+        match line.get(pos + total_parsed) {
+            None => return Err(BencodeError::UnexpectedEof { offset: pos + total_parsed }),
+            Some(b'e') => {
+                total_parsed += 1;
+                break;
+            }
+            Some(_) => {}
         }
+        let ParseResult { data, len } = parse_value(line, pos + total_parsed, strict, depth)?;
+        total_parsed += len;
+        ret_vec.push(data)
     }
-    Err(())
-}
-
-fn parse_list(line: &[u8]) -> Result<ParseResult<BList>, ()> {
-    let first_char = sub_arr(line.to_vec(), 0, 1)[0];
-    if first_char == "l".as_bytes()[0] {
-        let mut new_line = sub_arr(line.to_vec(), 1, line.len());
-        let mut ret_vec = Vec::new();
-        let mut total_parsed = 1;
-        loop {
-            let bencode = parse_bencode(&new_line);
-            if let Ok(res) = bencode {
-                let ParseResult { data, len } = res;
-                if Bencode::End == data {
-                    total_parsed += len;
-                    break;
-                } else {
-                    total_parsed += len;
-                    new_line = sub_arr(new_line.to_vec(), len, new_line.len());
-                    ret_vec.push(data)
-                }
+    Ok(ParseResult::new(ret_vec, total_parsed))
+}
+fn parse_dict(
+    line: &[u8],
+    pos: usize,
+    strict: bool,
+    depth: usize,
+) -> Result<ParseResult<BDictRef<'_>>, BencodeError> {
+    if line.get(pos) != Some(&b'd') {
+        return Err(BencodeError::UnexpectedByte { offset: pos, byte: line.get(pos).copied().unwrap_or(0) });
+    }
+    let mut ret_map = BTreeMap::new();
+    let mut last_key: Option<&[u8]> = None;
+    let mut total_parsed = 1;
+    loop {
+        let key_offset = pos + total_parsed;
+        match line.get(key_offset) {
+            None => return Err(BencodeError::UnexpectedEof { offset: key_offset }),
+            Some(b'e') => {
+                total_parsed += 1;
+                break;
             }
+            Some(b'0'..=b'9') => {}
+            Some(_) => return Err(BencodeError::NonStringKey { offset: key_offset }),
         }
-        return Ok(ParseResult::new(ret_vec, total_parsed));
-    }
-    Err(())
-}
-fn parse_dict(line: &[u8]) -> Result<ParseResult<BDict>, ()> {
-    let first_char = sub_arr(line.to_vec(), 0, 1)[0];
-    if first_char == "d".as_bytes()[0] {
-        let mut new_line = sub_arr(line.to_vec(), 1, line.len());
-        let mut ret_map = HashMap::new();
-        let mut total_parsed = 1;
-        loop {
-            let bencode_str = parse_bencode(&new_line);
-            if let Ok(res) = bencode_str {
-                let ParseResult { data, len } = res;
-                total_parsed += len;
-                if let Bencode::Str(map_key) = data {
-                    new_line = sub_arr(new_line.clone(), len, new_line.len());
-                    let benccode_value = parse_bencode(&new_line);
-                    if let Ok(res) = benccode_value {
-                        let ParseResult { data, len } = res;
-                        total_parsed += len;
-                        ret_map.insert(String::from_utf8(map_key).unwrap(), data);
-                        new_line = sub_arr(new_line.clone(), len, new_line.len())
-                    } else {
-                        panic!("Invalid bencode")
-                    }
-                } else if data == Bencode::End {
-                    break;
-                } else {
-                    panic!("Invalid bencode")
+        let ParseResult { data: map_key, len } = parse_string(line, key_offset)?;
+        total_parsed += len;
+        if strict {
+            if let Some(last_key) = last_key {
+                if map_key == last_key {
+                    return Err(BencodeError::DuplicateKey { offset: key_offset });
+                }
+                if map_key < last_key {
+                    return Err(BencodeError::UnsortedKey { offset: key_offset });
                 }
             }
+            last_key = Some(map_key);
         }
-        return Ok(ParseResult::new(ret_map, total_parsed));
+
+        let ParseResult { data, len } = parse_value(line, pos + total_parsed, strict, depth)?;
+        total_parsed += len;
+        ret_map.insert(map_key, data);
     }
-    Err(())
+    let dict = BDictRef {
+        entries: ret_map,
+        raw: &line[pos..pos + total_parsed],
+        span: pos..pos + total_parsed,
+    };
+    Ok(ParseResult::new(dict, total_parsed))
 }
 
-fn encode_string(string: &[u8]) -> Vec<u8> {
-    let len = string.len();
-    let mut ret = Vec::new();
-    ret.extend(len.to_string().as_bytes());
-    ret.push(b':');
-    ret.extend(string);
-    ret.to_vec()
+fn write_string<W: Write>(string: &[u8], writer: &mut W) -> io::Result<()> {
+    write!(writer, "{}:", string.len())?;
+    writer.write_all(string)
 }
-fn encode_int(int: &i128) -> Vec<u8> {
-    let mut ret = Vec::new();
-    ret.push(b'i');
-    ret.extend(int.to_string().as_bytes());
-    ret.push(b'e');
-    ret
+fn write_int<W: Write>(int: BInt, writer: &mut W) -> io::Result<()> {
+    write!(writer, "i{}e", int)
 }
-fn encode_list(list: &[Bencode]) -> Vec<u8> {
-    let mut ret = Vec::new();
-    ret.push(b'l');
+fn write_list<W: Write>(list: &[Bencode], writer: &mut W) -> io::Result<()> {
+    writer.write_all(b"l")?;
     for bencode in list {
-        ret.extend(encode_bencode(bencode))
+        write_bencode(bencode, writer)?;
     }
-    ret.push(b'e');
-    ret
+    writer.write_all(b"e")
 }
-fn encode_dict(dict: &BDict) -> Vec<u8> {
-    let mut ret = Vec::new();
-    ret.push(b'd');
-    let mut key_vec = dict.keys().collect::<Vec<&String>>();
-    //ensure keys are sorted alphabetically
-    key_vec.sort();
-    for key in key_vec {
-        let bencode_val = dict.get(key).unwrap();
-        let encoded_val = encode_bencode(bencode_val);
-        let encoded_key = encode_string(key.as_bytes());
-        ret.extend(encoded_key);
-        ret.extend(encoded_val);
-    }
-    ret.push(b'e');
-    ret
+fn write_dict<W: Write>(dict: &BDict, writer: &mut W) -> io::Result<()> {
+    writer.write_all(b"d")?;
+    // BTreeMap iterates keys in sorted byte order, as the spec requires
+    for (key, bencode_val) in dict {
+        write_string(key, writer)?;
+        write_bencode(bencode_val, writer)?;
+    }
+    writer.write_all(b"e")
+}
+
+/// Writes `bencode` straight into `writer` without intermediate buffers.
+///
+/// Wrap unbuffered writers such as files in a `BufWriter`; every string and
+/// integer is a separate write.
+pub fn write_bencode<W: Write>(bencode: &Bencode, writer: &mut W) -> io::Result<()> {
+    match bencode {
+        Bencode::Str(b_str) => write_string(b_str, writer),
+        Bencode::Int(b_int) => write_int(*b_int, writer),
+        Bencode::List(b_list) => write_list(b_list, writer),
+        Bencode::Dict(b_dict) => write_dict(b_dict, writer),
+    }
 }
+
 pub fn encode_bencode(bencode: &Bencode) -> Vec<u8> {
-    let mut ret = Vec::new();
+    let mut ret = Vec::with_capacity(encoded_len(bencode));
+    write_bencode(bencode, &mut ret).expect("writing to a Vec cannot fail");
+    ret
+}
+
+fn decimal_len(mut num: u128) -> usize {
+    let mut len = 1;
+    while num >= 10 {
+        num /= 10;
+        len += 1;
+    }
+    len
+}
+
+fn string_encoded_len(string: &[u8]) -> usize {
+    decimal_len(string.len() as u128) + 1 + string.len()
+}
+
+/// Number of bytes [`encode_bencode`] produces for `bencode`.
+pub fn encoded_len(bencode: &Bencode) -> usize {
     match bencode {
-        Bencode::Str(b_str) => {
-            ret.extend(encode_string(&b_str))
-        }
-        Bencode::Int(b_int) => {
-            ret.extend(encode_int(b_int))
-        }
-        Bencode::List(b_list) => {
-            ret.extend(encode_list(b_list))
-        }
+        Bencode::Str(b_str) => string_encoded_len(b_str),
+        Bencode::Int(b_int) => 2 + decimal_len(b_int.unsigned_abs()) + (*b_int < 0) as usize,
+        Bencode::List(b_list) => 2 + b_list.iter().map(encoded_len).sum::<usize>(),
         Bencode::Dict(b_dict) => {
-            ret.extend(encode_dict(b_dict))
+            2 + b_dict
+                .iter()
+                .map(|(key, value)| string_encoded_len(key) + encoded_len(value))
+                .sum::<usize>()
         }
-        Bencode::End => {}
     }
-    ret
 }
 
 
@@ -289,22 +603,23 @@ mod bencode_tests {
 
     #[test]
     fn test_parse_string() {
-        assert_eq!(parse_string("4:abcd".as_bytes()), Ok(ParseResult::new("abcd".as_bytes().to_vec(), 6)));
-        assert_eq!(parse_string("0:".as_bytes()), Ok(ParseResult::new("".as_bytes().to_vec(), 2)))
+        assert_eq!(parse_string("4:abcd".as_bytes(), 0), Ok(ParseResult::new("abcd".as_bytes(), 6)));
+        assert_eq!(parse_string("0:".as_bytes(), 0), Ok(ParseResult::new("".as_bytes(), 2)));
+        assert_eq!(parse_string("i1e4:abcd".as_bytes(), 3), Ok(ParseResult::new("abcd".as_bytes(), 6)))
     }
 
     #[test]
     fn test_parse_int() {
-        assert_eq!(parse_int("i123e".as_bytes()), Ok(ParseResult::new(123, 5)))
+        assert_eq!(parse_int("i123e".as_bytes(), 0), Ok(ParseResult::new(123, 5)))
     }
 
     #[test]
     fn test_list() {
         let test_str = String::from("l4:spam4:eggsi-234el4:spam4:eggsi-234e4:mdheee");
-        let lhs = parse_list(test_str.as_bytes());
+        let lhs = parse_bencode(test_str.as_bytes());
         let rhs = Ok(
             ParseResult::new(
-                vec![
+                Bencode::List(vec![
                     Bencode::new_str("spam"),
                     Bencode::new_str("eggs"),
                     Bencode::Int(-234),
@@ -315,7 +630,7 @@ mod bencode_tests {
                         Bencode::new_str("mdhe")
                     ]
                     )
-                ]
+                ])
                 , test_str.len()));
         assert_eq!(lhs, rhs);
     }
@@ -323,12 +638,12 @@ mod bencode_tests {
     #[test]
     fn test_dict() {
         let test_str = String::from("d4:listli12e3:zln6:whatupd1:k1:vee4:mdhe4:here3:numi-234ee");
-        let lhs = parse_dict(test_str.as_bytes());
+        let lhs = parse_bencode(test_str.as_bytes());
 
-        let mut map = HashMap::new();
-        let mut inner_map = HashMap::new();
-        inner_map.insert(String::from("k"), Bencode::new_str("v"));
-        map.insert(String::from("list"), Bencode::List(
+        let mut map = BTreeMap::new();
+        let mut inner_map = BTreeMap::new();
+        inner_map.insert(b"k".to_vec(), Bencode::new_str("v"));
+        map.insert(b"list".to_vec(), Bencode::List(
             vec![
                 Bencode::Int(12),
                 Bencode::new_str("zln"),
@@ -336,12 +651,184 @@ mod bencode_tests {
                 Bencode::Dict(inner_map)
             ]
         ));
-        map.insert(String::from("mdhe"), Bencode::new_str("here"));
-        map.insert(String::from("num"), Bencode::Int(-234));
-        let rhs = Ok(ParseResult::new(map, test_str.len()));
-        assert_eq!(lhs, rhs);
+        map.insert(b"mdhe".to_vec(), Bencode::new_str("here"));
+        map.insert(b"num".to_vec(), Bencode::Int(-234));
+        let rhs = ParseResult::new(Bencode::Dict(map), test_str.len());
+        let encoded = encode_bencode(&rhs.data);
+        assert_eq!(lhs, Ok(rhs));
 
-        let encoded = encode_dict(&rhs.unwrap().data);
         assert_eq!(String::from_utf8(encoded).unwrap(), test_str);
     }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse_bencode(b""), Err(BencodeError::UnexpectedEof { offset: 0 }));
+        assert_eq!(parse_bencode(b"e"), Err(BencodeError::UnexpectedByte { offset: 0, byte: b'e' }));
+        assert_eq!(parse_bencode(b"5:abc"), Err(BencodeError::UnexpectedEof { offset: 5 }));
+        assert_eq!(parse_bencode(b"x:abc"), Err(BencodeError::UnexpectedByte { offset: 0, byte: b'x' }));
+        assert_eq!(parse_bencode(b"05:abcde"), Err(BencodeError::LeadingZero { offset: 0 }));
+        assert_eq!(parse_bencode(b"1a:abc"), Err(BencodeError::InvalidLength { offset: 0 }));
+        assert_eq!(parse_bencode(b"i12"), Err(BencodeError::UnexpectedEof { offset: 3 }));
+        assert_eq!(parse_bencode(b"ie"), Err(BencodeError::InvalidInteger { offset: 1 }));
+        assert_eq!(parse_bencode(b"i1x2e"), Err(BencodeError::InvalidInteger { offset: 1 }));
+        assert_eq!(parse_bencode(b"i03e"), Err(BencodeError::LeadingZero { offset: 1 }));
+        assert_eq!(parse_bencode(b"i-0e"), Err(BencodeError::NegativeZero { offset: 1 }));
+        assert_eq!(parse_bencode(b"i-e"), Err(BencodeError::InvalidInteger { offset: 1 }));
+        assert_eq!(
+            parse_bencode(b"i999999999999999999999999999999999999999999e"),
+            Err(BencodeError::InvalidInteger { offset: 1 })
+        );
+        assert_eq!(parse_bencode(b"li1ei2e"), Err(BencodeError::UnexpectedEof { offset: 7 }));
+        assert_eq!(parse_bencode(b"l4:spam"), Err(BencodeError::UnexpectedEof { offset: 7 }));
+        assert_eq!(parse_bencode(b"d3:foo"), Err(BencodeError::UnexpectedEof { offset: 6 }));
+        assert_eq!(parse_bencode(b"d3:fooi1e"), Err(BencodeError::UnexpectedEof { offset: 9 }));
+        assert_eq!(parse_bencode(b"di1ei2ee"), Err(BencodeError::NonStringKey { offset: 1 }));
+        assert_eq!(parse_bencode(b"ll4:spame"), Err(BencodeError::UnexpectedEof { offset: 9 }));
+    }
+
+    #[test]
+    fn test_parse_ref_borrows_input() {
+        let input = b"d4:infod6:pieces3:abce4:listl1:xi7eee".to_vec();
+        let parsed = decode_bencode_ref(&input).unwrap();
+        let BencodeRef::Dict(dict) = &parsed else {
+            panic!("expected dict")
+        };
+        let Some(BencodeRef::Dict(info)) = dict.get("info") else {
+            panic!("expected info dict")
+        };
+        let Some(BencodeRef::Str(pieces)) = info.get("pieces") else {
+            panic!("expected pieces string")
+        };
+        assert_eq!(*pieces, b"abc");
+        assert_eq!(pieces.as_ptr(), input[18..].as_ptr());
+        assert_eq!(info.raw, b"d6:pieces3:abce");
+        assert_eq!(info.span, 7..22);
+        assert_eq!(dict.raw, &input[..]);
+        assert_eq!(
+            dict.get("list"),
+            Some(&BencodeRef::List(vec![BencodeRef::Str(b"x"), BencodeRef::Int(7)]))
+        );
+        assert_eq!(Bencode::from(&parsed), decode_bencode(&input).unwrap());
+    }
+
+    #[test]
+    fn test_dict_raw_keeps_original_bytes() {
+        // unsorted and duplicated keys do not survive a re-encode
+        let input = b"d4:infod1:bi1e1:ai2e1:ai3eee";
+        let BencodeRef::Dict(dict) = decode_bencode_ref(input).unwrap() else {
+            panic!("expected dict")
+        };
+        let Some(BencodeRef::Dict(info)) = dict.get("info") else {
+            panic!("expected info dict")
+        };
+        assert_eq!(info.raw, b"d1:bi1e1:ai2e1:ai3ee");
+        assert_ne!(encode_bencode(&Bencode::from(&BencodeRef::Dict(info.clone()))), info.raw);
+    }
+
+    #[test]
+    fn test_binary_keys() {
+        let input = b"d2:\xff\xfei1e1:ai2ee";
+        let Bencode::Dict(dict) = decode_bencode(input).unwrap() else {
+            panic!("expected dict")
+        };
+        assert_eq!(dict.get(b"\xff\xfe".as_slice()), Some(&Bencode::Int(1)));
+        // keys come back out in byte order
+        assert_eq!(encode_bencode(&Bencode::Dict(dict)), b"d1:ai2e2:\xff\xfei1ee");
+    }
+
+    #[test]
+    fn test_strict_keys() {
+        assert!(decode_bencode_strict(b"d1:ai1e1:bi2ee").is_ok());
+        assert_eq!(decode_bencode_strict(b"d1:bi1e1:ai2ee"), Err(BencodeError::UnsortedKey { offset: 7 }));
+        assert_eq!(decode_bencode_strict(b"d1:ai1e1:ai2ee"), Err(BencodeError::DuplicateKey { offset: 7 }));
+        assert_eq!(
+            decode_bencode_strict(b"ld1:bi1e1:ai2eee"),
+            Err(BencodeError::UnsortedKey { offset: 8 })
+        );
+        // "ab" sorts after "a" and before "b" as a raw byte string
+        assert!(decode_bencode_strict(b"d1:ai1e2:abi2e1:bi3ee").is_ok());
+        // lenient decoding keeps the last duplicate
+        let Bencode::Dict(dict) = decode_bencode(b"d1:ai1e1:ai2ee").unwrap() else {
+            panic!("expected dict")
+        };
+        assert_eq!(dict.get(b"a".as_slice()), Some(&Bencode::Int(2)));
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        let mut nested = vec![b'l'; MAX_PARSE_DEPTH];
+        nested.extend(vec![b'e'; MAX_PARSE_DEPTH]);
+        assert!(decode_bencode(&nested).is_ok());
+        let mut too_deep = vec![b'l'; 100_000];
+        too_deep.extend(vec![b'e'; 100_000]);
+        assert_eq!(
+            decode_bencode(&too_deep),
+            Err(BencodeError::DepthLimitExceeded { offset: MAX_PARSE_DEPTH })
+        );
+    }
+
+    #[test]
+    fn test_decode_trailing_data() {
+        assert_eq!(decode_bencode(b"i1e"), Ok(Bencode::Int(1)));
+        assert_eq!(decode_bencode(b"i1ei2e"), Err(BencodeError::TrailingData { offset: 3 }));
+        assert_eq!(parse_bencode(b"i1ei2e"), Ok(ParseResult::new(Bencode::Int(1), 3)));
+    }
+
+    // Info-hashes of every file in tests/fixtures, worked out independently of
+    // this crate: the published hash for real-world torrents, otherwise SHA-1
+    // and, for v2 and hybrid torrents, SHA-256 over the info dictionary bytes
+    // as found by a separate bencode reader. A fixture missing here fails the
+    // test, so new ones have to come with their hash.
+    const KNOWN_INFO_HASHES: [(&str, &str, Option<&str>); 7] = [
+        (
+            "hybrid.torrent",
+            "e5637b714bdd958e9fb56944969a48e8f8019d70",
+            Some("60c54bad0071d3039a57236acf372f0d713586560a35a07eff69e4f4a5a48961"),
+        ),
+        ("latin1_name.torrent", "072ca258f632560cdb268fffc51aaff6f64d5002", None),
+        ("multi_file.torrent", "366500f467a21be3d018176b90f6fbafa9e07067", None),
+        ("private_webseed.torrent", "82a732de4b632736bed6e5641403c8464126623d", None),
+        ("single_file.torrent", "b8281910f273c4d9447fab310dbbf5c8ccc33c74", None),
+        ("unsorted_info.torrent", "7fe51aa7848dca5473b92da310cbdb9417cc8c32", None),
+        (
+            "v2_only.torrent",
+            "26425682e9a56298c0030585c4ed9295c088b700",
+            Some("d76bd62f20fb9db2c41fe6ec9a310bc36c7a10beb1c4b972b9020f1e680c8cfa"),
+        ),
+    ];
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn test_fixtures() {
+        use sha1::{Digest, Sha1};
+        use sha2::Sha256;
+
+        let fixtures = std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures")).unwrap();
+        let mut count = 0;
+        for fixture in fixtures {
+            let path = fixture.unwrap().path();
+            let name = path.file_name().unwrap().to_str().unwrap();
+            let &(_, v1, v2) = KNOWN_INFO_HASHES
+                .iter()
+                .find(|(known, _, _)| *known == name)
+                .unwrap_or_else(|| panic!("no known info-hash for {}", name));
+            let content = std::fs::read(&path).unwrap();
+            let info = match decode_bencode_ref(&content).unwrap_or_else(|e| panic!("{}: {}", name, e)) {
+                BencodeRef::Dict(torrent) => match torrent.get("info") {
+                    Some(BencodeRef::Dict(info)) => info.raw,
+                    _ => panic!("{}: no info dictionary", name),
+                },
+                _ => panic!("{}: not a dictionary", name),
+            };
+            assert_eq!(hex(&Sha1::digest(info)), v1, "{}", name);
+            if let Some(v2) = v2 {
+                assert_eq!(hex(&Sha256::digest(info)), v2, "{}", name);
+            }
+            count += 1;
+        }
+        assert_eq!(count, KNOWN_INFO_HASHES.len());
+    }
 }
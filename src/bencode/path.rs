@@ -0,0 +1,365 @@
+use crate::bencode::{BDict, BInt, BList, Bencode};
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PathError {
+    /// The path itself is malformed, e.g. `info.files[x]`.
+    InvalidPath { path: String },
+    /// No value at `segment`, the path up to and including the missing part.
+    Missing { segment: String },
+    /// The value at `segment` is not a dictionary or list as the next part needs.
+    WrongType {
+        segment: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+}
+
+impl Display for PathError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathError::InvalidPath { path } => write!(f, "invalid path {:?}", path),
+            PathError::Missing { segment } => write!(f, "{} not found", segment),
+            PathError::WrongType {
+                segment,
+                expected,
+                found,
+            } => write!(f, "{} is a {}, expected a {}", segment, found, expected),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Segment<'p> {
+    Key(&'p str),
+    Index(usize),
+}
+
+// One parsed segment along with the path text up to and including it and
+// up to its parent, used to describe where a lookup failed.
+struct PathPart<'p> {
+    segment: Segment<'p>,
+    prefix: &'p str,
+    parent: &'p str,
+}
+
+// Splits `info.files[0].length` into `info`, `files`, `[0]`, `length`. Keys
+// cannot contain `.` or `[`.
+fn parse_path(path: &str) -> Result<Vec<PathPart<'_>>, PathError> {
+    let invalid = || PathError::InvalidPath {
+        path: path.to_string(),
+    };
+    let mut parts = Vec::new();
+    let mut pos = 0;
+    let bytes = path.as_bytes();
+    while pos < bytes.len() {
+        if bytes[pos] == b'[' {
+            let close = path[pos..].find(']').ok_or_else(invalid)? + pos;
+            let index = path[pos + 1..close].parse().map_err(|_| invalid())?;
+            parts.push(PathPart {
+                segment: Segment::Index(index),
+                prefix: &path[..close + 1],
+                parent: &path[..pos],
+            });
+            pos = close + 1;
+            if pos < bytes.len() && bytes[pos] == b'.' {
+                pos += 1;
+                if pos == bytes.len() {
+                    return Err(invalid());
+                }
+            }
+        } else {
+            let end = path[pos..].find(['.', '[', ']']).map_or(bytes.len(), |end| end + pos);
+            if end == pos || bytes.get(end) == Some(&b']') {
+                return Err(invalid());
+            }
+            parts.push(PathPart {
+                segment: Segment::Key(&path[pos..end]),
+                prefix: &path[..end],
+                parent: path[..pos].strip_suffix('.').unwrap_or(&path[..pos]),
+            });
+            pos = end;
+            if pos < bytes.len() && bytes[pos] == b'.' {
+                pos += 1;
+                if pos == bytes.len() {
+                    return Err(invalid());
+                }
+            }
+        }
+    }
+    if parts.is_empty() {
+        return Err(invalid());
+    }
+    Ok(parts)
+}
+
+fn type_name(value: &Bencode) -> &'static str {
+    match value {
+        Bencode::Str(_) => "string",
+        Bencode::Int(_) => "integer",
+        Bencode::List(_) => "list",
+        Bencode::Dict(_) => "dictionary",
+    }
+}
+
+fn wrong_type(part: &PathPart, value: &Bencode) -> PathError {
+    let expected = match part.segment {
+        Segment::Key(_) => "dictionary",
+        Segment::Index(_) => "list",
+    };
+    // the offending value is the parent of this part
+    let segment = if part.parent.is_empty() { "<root>" } else { part.parent };
+    PathError::WrongType {
+        segment: segment.to_string(),
+        expected,
+        found: type_name(value),
+    }
+}
+
+fn missing(part: &PathPart) -> PathError {
+    PathError::Missing {
+        segment: part.prefix.to_string(),
+    }
+}
+
+fn child<'v>(value: &'v Bencode, part: &PathPart) -> Result<&'v Bencode, PathError> {
+    match (part.segment, value) {
+        (Segment::Key(key), Bencode::Dict(dict)) => dict.get(key.as_bytes()).ok_or_else(|| missing(part)),
+        (Segment::Index(index), Bencode::List(list)) => list.get(index).ok_or_else(|| missing(part)),
+        _ => Err(wrong_type(part, value)),
+    }
+}
+
+fn child_mut<'v>(value: &'v mut Bencode, part: &PathPart) -> Result<&'v mut Bencode, PathError> {
+    match (part.segment, value) {
+        (Segment::Key(key), Bencode::Dict(dict)) => dict.get_mut(key.as_bytes()).ok_or_else(|| missing(part)),
+        (Segment::Index(index), Bencode::List(list)) => list.get_mut(index).ok_or_else(|| missing(part)),
+        (_, value) => Err(wrong_type(part, value)),
+    }
+}
+
+impl Bencode {
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Bencode::Str(str_val) => Some(str_val),
+            _ => None,
+        }
+    }
+
+    /// The string as text, or `None` if it is not a string or not utf8.
+    pub fn as_str(&self) -> Option<&str> {
+        self.as_bytes().and_then(|str_val| std::str::from_utf8(str_val).ok())
+    }
+
+    pub fn as_int(&self) -> Option<BInt> {
+        match self {
+            Bencode::Int(int_val) => Some(*int_val),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&BList> {
+        match self {
+            Bencode::List(list_val) => Some(list_val),
+            _ => None,
+        }
+    }
+
+    pub fn as_list_mut(&mut self) -> Option<&mut BList> {
+        match self {
+            Bencode::List(list_val) => Some(list_val),
+            _ => None,
+        }
+    }
+
+    pub fn as_dict(&self) -> Option<&BDict> {
+        match self {
+            Bencode::Dict(dict_val) => Some(dict_val),
+            _ => None,
+        }
+    }
+
+    pub fn as_dict_mut(&mut self) -> Option<&mut BDict> {
+        match self {
+            Bencode::Dict(dict_val) => Some(dict_val),
+            _ => None,
+        }
+    }
+
+    /// Looks up a nested value by path, e.g. `info.files[0].length`.
+    pub fn get_path(&self, path: &str) -> Result<&Bencode, PathError> {
+        let mut value = self;
+        for part in parse_path(path)? {
+            value = child(value, &part)?;
+        }
+        Ok(value)
+    }
+
+    pub fn get_path_mut(&mut self, path: &str) -> Result<&mut Bencode, PathError> {
+        let mut value = self;
+        for part in parse_path(path)? {
+            value = child_mut(value, &part)?;
+        }
+        Ok(value)
+    }
+
+    /// Stores `new_value` at `path` and returns the value it replaced.
+    ///
+    /// Missing dictionary entries along the path are created as empty
+    /// dictionaries or lists. A list index may be one past the end to append.
+    pub fn set_path(&mut self, path: &str, new_value: Bencode) -> Result<Option<Bencode>, PathError> {
+        let parts = parse_path(path)?;
+        let (last, parents) = parts.split_last().expect("parsed paths are never empty");
+        let mut value = self;
+        for (idx, part) in parents.iter().enumerate() {
+            if let (Segment::Key(key), Bencode::Dict(dict)) = (part.segment, &mut *value) {
+                if !dict.contains_key(key.as_bytes()) {
+                    let next = match parts[idx + 1].segment {
+                        Segment::Key(_) => Bencode::Dict(BDict::new()),
+                        Segment::Index(_) => Bencode::List(BList::new()),
+                    };
+                    dict.insert(key.as_bytes().to_vec(), next);
+                }
+            }
+            value = child_mut(value, part)?;
+        }
+        match (last.segment, value) {
+            (Segment::Key(key), Bencode::Dict(dict)) => Ok(dict.insert(key.as_bytes().to_vec(), new_value)),
+            (Segment::Index(index), Bencode::List(list)) => {
+                if index < list.len() {
+                    Ok(Some(std::mem::replace(&mut list[index], new_value)))
+                } else if index == list.len() {
+                    list.push(new_value);
+                    Ok(None)
+                } else {
+                    Err(missing(last))
+                }
+            }
+            (_, value) => Err(wrong_type(last, value)),
+        }
+    }
+
+    /// Removes and returns the value at `path`. Later list items shift down.
+    pub fn remove_path(&mut self, path: &str) -> Result<Bencode, PathError> {
+        let parts = parse_path(path)?;
+        let (last, parents) = parts.split_last().expect("parsed paths are never empty");
+        let mut value = self;
+        for part in parents {
+            value = child_mut(value, part)?;
+        }
+        match (last.segment, value) {
+            (Segment::Key(key), Bencode::Dict(dict)) => dict.remove(key.as_bytes()).ok_or_else(|| missing(last)),
+            (Segment::Index(index), Bencode::List(list)) if index < list.len() => Ok(list.remove(index)),
+            (Segment::Index(_), Bencode::List(_)) => Err(missing(last)),
+            (_, value) => Err(wrong_type(last, value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod path_tests {
+    use super::*;
+    use crate::bencode::{decode_bencode, encode_bencode};
+
+    fn torrent() -> Bencode {
+        decode_bencode(
+            b"d8:announce3:url4:infod5:filesld6:lengthi3e4:pathl1:aeed6:lengthi5e4:pathl1:beee4:name1:nee",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_accessors() {
+        let torrent = torrent();
+        assert_eq!(torrent.as_dict().map(|dict| dict.len()), Some(2));
+        assert_eq!(torrent.get_path("announce").unwrap().as_str(), Some("url"));
+        assert_eq!(torrent.get_path("announce").unwrap().as_int(), None);
+        assert_eq!(Bencode::Str(vec![0xff]).as_str(), None);
+        assert_eq!(Bencode::Str(vec![0xff]).as_bytes(), Some([0xff].as_slice()));
+        assert_eq!(torrent.get_path("info.files").unwrap().as_list().map(Vec::len), Some(2));
+    }
+
+    #[test]
+    fn test_get_path() {
+        let torrent = torrent();
+        assert_eq!(torrent.get_path("info.files[1].length"), Ok(&Bencode::Int(5)));
+        assert_eq!(torrent.get_path("info.files[0].path[0]"), Ok(&Bencode::new_str("a")));
+        assert_eq!(
+            torrent.get_path("info.files[2].length"),
+            Err(PathError::Missing {
+                segment: String::from("info.files[2]")
+            })
+        );
+        assert_eq!(
+            torrent.get_path("info.length"),
+            Err(PathError::Missing {
+                segment: String::from("info.length")
+            })
+        );
+        assert_eq!(
+            torrent.get_path("info.name.first"),
+            Err(PathError::WrongType {
+                segment: String::from("info.name"),
+                expected: "dictionary",
+                found: "string",
+            })
+        );
+        assert_eq!(
+            torrent.get_path("info.files[0][1]"),
+            Err(PathError::WrongType {
+                segment: String::from("info.files[0]"),
+                expected: "list",
+                found: "dictionary",
+            })
+        );
+        assert_eq!(
+            Bencode::Int(1).get_path("[0]"),
+            Err(PathError::WrongType {
+                segment: String::from("<root>"),
+                expected: "list",
+                found: "integer",
+            })
+        );
+        for path in ["", "info.", ".info", "info..name", "info.files[", "info.files[a]", "info]"] {
+            assert_eq!(
+                torrent.get_path(path),
+                Err(PathError::InvalidPath {
+                    path: path.to_string()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_set_and_remove_path() {
+        let mut torrent = torrent();
+        assert_eq!(
+            torrent.set_path("announce", Bencode::new_str("new")),
+            Ok(Some(Bencode::new_str("url")))
+        );
+        assert_eq!(torrent.set_path("info.files[2]", Bencode::Int(1)), Ok(None));
+        assert_eq!(
+            torrent.set_path("info.files[4]", Bencode::Int(1)),
+            Err(PathError::Missing {
+                segment: String::from("info.files[4]")
+            })
+        );
+        assert_eq!(torrent.set_path("comment.text", Bencode::new_str("hi")), Ok(None));
+        assert_eq!(torrent.set_path("url-list[0]", Bencode::new_str("http")), Ok(None));
+        *torrent.get_path_mut("info.files[0].length").unwrap() = Bencode::Int(4);
+        assert_eq!(torrent.remove_path("info.files[1]").map(|file| file.as_dict().is_some()), Ok(true));
+        assert_eq!(torrent.remove_path("info.name"), Ok(Bencode::new_str("n")));
+        assert_eq!(
+            torrent.remove_path("info.name"),
+            Err(PathError::Missing {
+                segment: String::from("info.name")
+            })
+        );
+        assert_eq!(
+            encode_bencode(&torrent),
+            b"d8:announce3:new7:commentd4:text2:hie4:infod5:filesld6:lengthi4e4:pathl1:aeei1eee8:url-listl4:httpee"
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use crate::bencode::{
+    decode_bencode, decode_bencode_strict, encode_bencode, encoded_len, parse_bencode, BencodeError,
+    DecodeStatus, StreamDecoder,
+};
+use crate::bencode::Bencode;
+use proptest::prelude::*;
+
+fn arb_bencode() -> impl Strategy<Value = Bencode> {
+    let leaf = prop_oneof![
+        any::<i128>().prop_map(Bencode::Int),
+        prop::collection::vec(any::<u8>(), 0..24).prop_map(Bencode::Str),
+    ];
+    leaf.prop_recursive(5, 96, 8, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..8).prop_map(Bencode::List),
+            prop::collection::btree_map(prop::collection::vec(any::<u8>(), 0..8), inner, 0..8)
+                .prop_map(Bencode::Dict),
+        ]
+    })
+}
+
+// Valid encodings with a byte replaced or the tail cut off, which reach far
+// more of the parser than uniformly random bytes.
+fn arb_damaged() -> impl Strategy<Value = Vec<u8>> {
+    (arb_bencode(), any::<prop::sample::Index>(), any::<u8>(), any::<bool>()).prop_map(
+        |(value, index, byte, truncate)| {
+            let mut encoded = encode_bencode(&value);
+            let index = index.index(encoded.len());
+            if truncate {
+                encoded.truncate(index);
+            } else {
+                encoded[index] = byte;
+            }
+            encoded
+        },
+    )
+}
+
+fn check_error_offset(input: &[u8], result: Result<Bencode, BencodeError>) {
+    if let Err(e) = result {
+        assert!(e.offset() <= input.len(), "{} past end of {} bytes", e, input.len());
+    }
+}
+
+proptest! {
+    #[test]
+    fn round_trip(value in arb_bencode()) {
+        let encoded = encode_bencode(&value);
+        prop_assert_eq!(encoded_len(&value), encoded.len());
+        prop_assert_eq!(decode_bencode(&encoded), Ok(value.clone()));
+        // the encoder only produces canonical output
+        prop_assert_eq!(decode_bencode_strict(&encoded), Ok(value));
+    }
+
+    #[test]
+    fn canonical_form_is_idempotent(input in prop_oneof![arb_damaged(), prop::collection::vec(any::<u8>(), 0..64)]) {
+        if let Ok(value) = decode_bencode(&input) {
+            let canonical = encode_bencode(&value);
+            let reparsed = decode_bencode(&canonical).unwrap();
+            prop_assert_eq!(encode_bencode(&reparsed), canonical);
+        }
+    }
+
+    #[test]
+    fn hostile_input_never_panics(input in prop_oneof![arb_damaged(), prop::collection::vec(any::<u8>(), 0..64)]) {
+        check_error_offset(&input, parse_bencode(&input).map(|res| res.data));
+        check_error_offset(&input, decode_bencode(&input));
+        check_error_offset(&input, decode_bencode_strict(&input));
+    }
+
+    #[test]
+    fn stream_decoder_agrees_with_decoder(input in arb_damaged(), chunk in 1usize..16) {
+        let mut decoder = StreamDecoder::default();
+        let mut streamed = Ok(DecodeStatus::NeedMore);
+        for piece in input.chunks(chunk) {
+            streamed = decoder.push(piece);
+            if streamed != Ok(DecodeStatus::NeedMore) {
+                break;
+            }
+        }
+        match (streamed, parse_bencode(&input)) {
+            (Ok(DecodeStatus::Done(value)), Ok(parsed)) => prop_assert_eq!(value, parsed.data),
+            (Ok(DecodeStatus::NeedMore), Err(BencodeError::UnexpectedEof { .. })) => {}
+            (Err(streamed), Err(parsed)) => prop_assert_eq!(streamed, parsed),
+            (streamed, parsed) => prop_assert!(false, "stream {:?} but parse {:?}", streamed, parsed),
+        }
+    }
+}
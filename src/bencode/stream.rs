@@ -0,0 +1,395 @@
+use crate::bencode::{
+    check_digits, parse_bencode, BInt, Bencode, BencodeError, MAX_INT_CHARS, MAX_LENGTH_DIGITS, MAX_PARSE_DEPTH,
+};
+
+/// Bounds applied by [`StreamDecoder`] before any value is built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Maximum nesting of lists and dictionaries; a top level list is depth 1.
+    /// Capped at [`MAX_PARSE_DEPTH`], past which values cannot be parsed.
+    pub max_depth: usize,
+    /// Maximum length of a single byte string.
+    pub max_string_len: usize,
+    /// Maximum encoded size of one complete value.
+    pub max_total_len: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_depth: 32,
+            max_string_len: 32 * 1024 * 1024,
+            max_total_len: 64 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeStatus {
+    /// The buffered bytes are a valid prefix but no value is complete yet.
+    NeedMore,
+    /// A complete value. Bytes after it stay buffered for the next call.
+    Done(Bencode),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Container {
+    List,
+    // `true` while the next item is a key
+    Dict(bool),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Token {
+    None,
+    Int { start: usize },
+    StrLen { start: usize },
+    StrBody { remaining: usize },
+}
+
+/// Push style decoder for values that arrive in chunks, e.g. from a socket.
+///
+/// Each pushed byte is scanned once, so feeding a value one byte at a time is
+/// still linear. The scanner checks structure and limits as bytes arrive; the
+/// value itself is only built once its final byte is seen. Error offsets are
+/// relative to the start of the value being decoded.
+pub struct StreamDecoder {
+    limits: DecodeLimits,
+    buf: Vec<u8>,
+    pos: usize,
+    stack: Vec<Container>,
+    token: Token,
+    error: Option<BencodeError>,
+}
+
+impl StreamDecoder {
+    pub fn new(limits: DecodeLimits) -> Self {
+        StreamDecoder {
+            limits: DecodeLimits {
+                max_depth: limits.max_depth.min(MAX_PARSE_DEPTH),
+                ..limits
+            },
+            buf: Vec::new(),
+            pos: 0,
+            stack: Vec::new(),
+            token: Token::None,
+            error: None,
+        }
+    }
+
+    /// Appends `chunk` and tries to complete the next value.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<DecodeStatus, BencodeError> {
+        if self.error.is_none() {
+            self.buf.extend_from_slice(chunk);
+        }
+        self.poll()
+    }
+
+    /// Tries to complete the next value from what is already buffered.
+    ///
+    /// Once an error is returned the decoder keeps returning it, since there
+    /// is no reliable way to find the start of the next value.
+    pub fn poll(&mut self) -> Result<DecodeStatus, BencodeError> {
+        if let Some(e) = &self.error {
+            return Err(e.clone());
+        }
+        match self.scan() {
+            Ok(Some(end)) => {
+                let value = parse_bencode(&self.buf[..end]);
+                self.buf.drain(..end);
+                self.pos = 0;
+                match value {
+                    Ok(value) => Ok(DecodeStatus::Done(value.data)),
+                    Err(e) => {
+                        self.error = Some(e.clone());
+                        Err(e)
+                    }
+                }
+            }
+            Ok(None) => Ok(DecodeStatus::NeedMore),
+            Err(e) => {
+                self.error = Some(e.clone());
+                Err(e)
+            }
+        }
+    }
+
+    /// Bytes received but not yet returned as part of a value.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buf
+    }
+
+    // Advances over the buffer. Returns the end of the first value once it is
+    // complete. The value starts at the front of the buffer, so `pos` is the
+    // size it has reached so far, whatever follows it in the buffer.
+    fn scan(&mut self) -> Result<Option<usize>, BencodeError> {
+        while self.pos < self.buf.len() {
+            let pos = self.pos;
+            self.check_total_len(pos + 1)?;
+            let byte = self.buf[pos];
+            match self.token {
+                Token::StrBody { remaining } => {
+                    let available = self.buf.len() - pos;
+                    if available < remaining {
+                        self.pos = self.buf.len();
+                        self.check_total_len(self.pos)?;
+                        self.token = Token::StrBody { remaining: remaining - available };
+                        break;
+                    }
+                    self.pos += remaining;
+                    self.check_total_len(self.pos)?;
+                    self.token = Token::None;
+                    if let Some(end) = self.finish_value(self.pos) {
+                        return Ok(Some(end));
+                    }
+                    continue;
+                }
+                Token::Int { start } => {
+                    if byte == b'e' {
+                        let num_bytes = &self.buf[start + 1..pos];
+                        let (negative, digits) = match num_bytes.first() {
+                            Some(b'-') => (true, &num_bytes[1..]),
+                            _ => (false, num_bytes),
+                        };
+                        check_digits(digits, negative, start + 1)?;
+                        // overflow would otherwise only show up once the whole value is in
+                        std::str::from_utf8(num_bytes)
+                            .ok()
+                            .and_then(|num| num.parse::<BInt>().ok())
+                            .ok_or(BencodeError::InvalidInteger { offset: start + 1 })?;
+                        self.token = Token::None;
+                        self.pos += 1;
+                        if let Some(end) = self.finish_value(self.pos) {
+                            return Ok(Some(end));
+                        }
+                        continue;
+                    }
+                    let sign_ok = byte == b'-' && pos == start + 1;
+                    if !(byte.is_ascii_digit() || sign_ok) || pos - start > MAX_INT_CHARS {
+                        return Err(BencodeError::InvalidInteger { offset: start + 1 });
+                    }
+                }
+                Token::StrLen { start } => {
+                    if byte == b':' {
+                        let len = self.string_len(start, pos)?;
+                        self.pos += 1;
+                        if len == 0 {
+                            self.token = Token::None;
+                            if let Some(end) = self.finish_value(self.pos) {
+                                return Ok(Some(end));
+                            }
+                        } else {
+                            self.token = Token::StrBody { remaining: len };
+                        }
+                        continue;
+                    }
+                    if !byte.is_ascii_digit() || pos - start > MAX_LENGTH_DIGITS {
+                        return Err(BencodeError::InvalidLength { offset: start });
+                    }
+                }
+                Token::None => {
+                    if let Some(end) = self.start_value(pos, byte)? {
+                        return Ok(Some(end));
+                    }
+                }
+            }
+            self.pos += 1;
+        }
+        Ok(None)
+    }
+
+    fn check_total_len(&self, len: usize) -> Result<(), BencodeError> {
+        if len > self.limits.max_total_len {
+            return Err(BencodeError::SizeLimitExceeded { offset: self.limits.max_total_len });
+        }
+        Ok(())
+    }
+
+    fn start_value(&mut self, pos: usize, byte: u8) -> Result<Option<usize>, BencodeError> {
+        if let Some(Container::Dict(true)) = self.stack.last() {
+            return match byte {
+                b'e' => Ok(self.close_container()),
+                b'0'..=b'9' => {
+                    self.token = Token::StrLen { start: pos };
+                    Ok(None)
+                }
+                _ => Err(BencodeError::NonStringKey { offset: pos }),
+            };
+        }
+        match byte {
+            b'e' if matches!(self.stack.last(), Some(Container::List)) => Ok(self.close_container()),
+            b'i' => {
+                self.token = Token::Int { start: pos };
+                Ok(None)
+            }
+            b'l' | b'd' => {
+                if self.stack.len() >= self.limits.max_depth {
+                    return Err(BencodeError::DepthLimitExceeded { offset: pos });
+                }
+                self.stack.push(if byte == b'l' { Container::List } else { Container::Dict(true) });
+                Ok(None)
+            }
+            b'0'..=b'9' => {
+                self.token = Token::StrLen { start: pos };
+                Ok(None)
+            }
+            _ => Err(BencodeError::UnexpectedByte { offset: pos, byte }),
+        }
+    }
+
+    fn string_len(&self, start: usize, separator: usize) -> Result<usize, BencodeError> {
+        let len_digits = &self.buf[start..separator];
+        check_digits(len_digits, false, start).map_err(|e| match e {
+            BencodeError::LeadingZero { .. } => e,
+            _ => BencodeError::InvalidLength { offset: start },
+        })?;
+        let len = std::str::from_utf8(len_digits)
+            .ok()
+            .and_then(|len| len.parse::<usize>().ok())
+            .ok_or(BencodeError::InvalidLength { offset: start })?;
+        if len > self.limits.max_string_len {
+            return Err(BencodeError::StringTooLong { offset: start });
+        }
+        Ok(len)
+    }
+
+    // Called with `pos` on the closing `e`.
+    fn close_container(&mut self) -> Option<usize> {
+        self.stack.pop();
+        self.finish_value(self.pos + 1)
+    }
+
+    // Marks the value ending at `end` as complete within its parent, and
+    // returns `end` if that was the top level value.
+    fn finish_value(&mut self, end: usize) -> Option<usize> {
+        match self.stack.last_mut() {
+            None => Some(end),
+            Some(Container::Dict(expect_key)) => {
+                *expect_key = !*expect_key;
+                None
+            }
+            Some(Container::List) => None,
+        }
+    }
+}
+
+impl Default for StreamDecoder {
+    fn default() -> Self {
+        StreamDecoder::new(DecodeLimits::default())
+    }
+}
+
+#[cfg(test)]
+mod stream_tests {
+    use super::*;
+    use crate::bencode::decode_bencode;
+
+    #[test]
+    fn test_byte_at_a_time() {
+        let input = b"d4:listli12e3:zln6:whatupd1:k1:vee4:mdhe4:here3:numi-234ee";
+        let mut decoder = StreamDecoder::default();
+        for byte in &input[..input.len() - 1] {
+            assert_eq!(decoder.push(&[*byte]), Ok(DecodeStatus::NeedMore));
+        }
+        assert_eq!(
+            decoder.push(&input[input.len() - 1..]),
+            Ok(DecodeStatus::Done(decode_bencode(input).unwrap()))
+        );
+        assert!(decoder.buffered().is_empty());
+    }
+
+    #[test]
+    fn test_multiple_values_in_one_chunk() {
+        let mut decoder = StreamDecoder::default();
+        assert_eq!(decoder.push(b"i1e4:spa"), Ok(DecodeStatus::Done(Bencode::Int(1))));
+        assert_eq!(decoder.poll(), Ok(DecodeStatus::NeedMore));
+        assert_eq!(decoder.push(b"mle"), Ok(DecodeStatus::Done(Bencode::new_str("spam"))));
+        assert_eq!(decoder.poll(), Ok(DecodeStatus::Done(Bencode::List(vec![]))));
+        assert_eq!(decoder.poll(), Ok(DecodeStatus::NeedMore));
+    }
+
+    #[test]
+    fn test_empty_string_and_empty_dict() {
+        let mut decoder = StreamDecoder::default();
+        assert_eq!(decoder.push(b"0:"), Ok(DecodeStatus::Done(Bencode::new_str(""))));
+        assert_eq!(decoder.push(b"d1:a0:e"), Ok(DecodeStatus::Done(decode_bencode(b"d1:a0:e").unwrap())));
+        assert_eq!(decoder.push(b"de"), Ok(DecodeStatus::Done(decode_bencode(b"de").unwrap())));
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        let mut decoder = StreamDecoder::default();
+        assert_eq!(decoder.push(b"li1e"), Ok(DecodeStatus::NeedMore));
+        assert_eq!(decoder.push(b"x"), Err(BencodeError::UnexpectedByte { offset: 4, byte: b'x' }));
+        // the decoder stays failed
+        assert_eq!(decoder.push(b"e"), Err(BencodeError::UnexpectedByte { offset: 4, byte: b'x' }));
+
+        let errors: [(&[u8], BencodeError); 7] = [
+            (b"e", BencodeError::UnexpectedByte { offset: 0, byte: b'e' }),
+            (b"di1e", BencodeError::NonStringKey { offset: 1 }),
+            (b"d1:ae", BencodeError::UnexpectedByte { offset: 4, byte: b'e' }),
+            (b"i-0e", BencodeError::NegativeZero { offset: 1 }),
+            (b"i1-e", BencodeError::InvalidInteger { offset: 1 }),
+            (b"03:abc", BencodeError::LeadingZero { offset: 0 }),
+            (b"3x", BencodeError::InvalidLength { offset: 0 }),
+        ];
+        for (input, error) in errors {
+            assert_eq!(StreamDecoder::default().push(input), Err(error));
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = DecodeLimits {
+            max_depth: 2,
+            max_string_len: 4,
+            max_total_len: 16,
+        };
+        assert_eq!(
+            StreamDecoder::new(limits).push(b"lli1eee"),
+            Ok(DecodeStatus::Done(decode_bencode(b"lli1eee").unwrap()))
+        );
+        assert_eq!(
+            StreamDecoder::new(limits).push(b"llle"),
+            Err(BencodeError::DepthLimitExceeded { offset: 2 })
+        );
+        assert_eq!(
+            StreamDecoder::new(limits).push(b"5:"),
+            Err(BencodeError::StringTooLong { offset: 0 })
+        );
+        let mut decoder = StreamDecoder::new(limits);
+        assert_eq!(decoder.push(b"li1ei2ei3ei4e"), Ok(DecodeStatus::NeedMore));
+        assert_eq!(decoder.push(b"i55e"), Err(BencodeError::SizeLimitExceeded { offset: 16 }));
+        // a whole oversized value in one chunk
+        assert_eq!(
+            StreamDecoder::new(limits).push(b"li1ei2ei3ei4ei5ee"),
+            Err(BencodeError::SizeLimitExceeded { offset: 16 })
+        );
+        assert_eq!(
+            StreamDecoder::new(limits).push(b"13:abcdefghijklm"),
+            Err(BencodeError::StringTooLong { offset: 0 })
+        );
+        let long_string = DecodeLimits { max_string_len: 64, ..limits };
+        assert_eq!(
+            StreamDecoder::new(long_string).push(b"20:abcdefghijklmnopqrst"),
+            Err(BencodeError::SizeLimitExceeded { offset: 16 })
+        );
+        // only the value counts, not what is buffered after it
+        let mut decoder = StreamDecoder::new(limits);
+        assert_eq!(decoder.push(b"i1ei2ei3ei4ei5ei6e"), Ok(DecodeStatus::Done(Bencode::Int(1))));
+        // deeper than the parser goes is refused while scanning
+        let deep = DecodeLimits {
+            max_depth: 1000,
+            ..DecodeLimits::default()
+        };
+        assert_eq!(
+            StreamDecoder::new(deep).push(&[b'l'; MAX_PARSE_DEPTH + 1]),
+            Err(BencodeError::DepthLimitExceeded { offset: MAX_PARSE_DEPTH })
+        );
+        // a huge announced length fails before any of it is buffered
+        assert_eq!(
+            StreamDecoder::default().push(b"99999999999:"),
+            Err(BencodeError::StringTooLong { offset: 0 })
+        );
+    }
+}
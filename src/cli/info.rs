@@ -0,0 +1,309 @@
+use crate::cli::USAGE;
+use data_encoding::HEXLOWER;
+use serde_json::{json, Value};
+use torrent::magnet::Magnet;
+use torrent::metainfo::{Info, Metainfo, TorrentVersion};
+
+pub fn info_command(args: &[String]) -> Result<(), String> {
+    let (input, as_json) = match args {
+        [input] => (input, false),
+        [input, option] if option == "--json" => (input, true),
+        [option, input] if option == "--json" => (input, true),
+        _ => return Err(USAGE.to_string()),
+    };
+    if input.starts_with("magnet:") {
+        let magnet = Magnet::parse(input).map_err(|e| format!("{}: {}", input, e))?;
+        if as_json {
+            print_json(magnet_json(&magnet));
+        } else {
+            print_magnet(&magnet);
+        }
+    } else {
+        let metainfo = Metainfo::from_file(input).map_err(|e| format!("{}: {}", input, e))?;
+        if as_json {
+            print_json(metainfo_json(&metainfo));
+        } else {
+            print_metainfo(&metainfo);
+        }
+    }
+    Ok(())
+}
+
+fn print_json(value: Value) {
+    println!("{}", serde_json::to_string_pretty(&value).expect("JSON values always serialize"));
+}
+
+// Files with their paths from the download directory, without padding.
+// Hybrid torrents are listed from the v2 file tree, which has none.
+fn files(info: &Info) -> Vec<(Vec<String>, u64)> {
+    if info.file_tree.is_empty() {
+        return info
+            .layout()
+            .files
+            .into_iter()
+            .filter(|file| !file.attributes.padding)
+            .map(|file| {
+                let path = file.path.iter().map(|c| c.to_string_lossy().into_owned()).collect();
+                (path, file.length)
+            })
+            .collect();
+    }
+    match info.file_tree.as_slice() {
+        [file] if file.path.len() == 1 => vec![(file.path.clone(), file.length)],
+        tree => tree
+            .iter()
+            .map(|file| {
+                let mut path = vec![info.name.clone()];
+                path.extend(file.path.iter().cloned());
+                (path, file.length)
+            })
+            .collect(),
+    }
+}
+
+// v2 pieces never span files, so v2-only torrents count them per file.
+fn piece_count(info: &Info) -> u64 {
+    match info.version {
+        TorrentVersion::V2 => info
+            .file_tree
+            .iter()
+            .map(|file| file.length.div_ceil(info.piece_length))
+            .sum(),
+        _ => info.pieces.len() as u64,
+    }
+}
+
+fn version_name(version: TorrentVersion) -> &'static str {
+    match version {
+        TorrentVersion::V1 => "v1",
+        TorrentVersion::V2 => "v2",
+        TorrentVersion::Hybrid => "hybrid",
+    }
+}
+
+fn v1_hash(metainfo: &Metainfo) -> Option<String> {
+    match metainfo.info.version {
+        TorrentVersion::V2 => None,
+        _ => Some(HEXLOWER.encode(&metainfo.info_hash())),
+    }
+}
+
+fn metainfo_json(metainfo: &Metainfo) -> Value {
+    let info = &metainfo.info;
+    let files = files(info);
+    let total_size: u64 = files.iter().map(|(_, length)| length).sum();
+    let files: Vec<Value> = files
+        .into_iter()
+        .map(|(path, length)| json!({"path": path.join("/"), "length": length}))
+        .collect();
+    json!({
+        "name": info.name,
+        "info_hash": v1_hash(metainfo),
+        "info_hash_v2": metainfo.info_hash_v2().map(|hash| HEXLOWER.encode(&hash)),
+        "version": version_name(info.version),
+        "total_size": total_size,
+        "piece_length": info.piece_length,
+        "piece_count": piece_count(info),
+        "private": info.private,
+        "files": files,
+        "trackers": tracker_tiers(metainfo),
+        "web_seeds": metainfo.web_seeds,
+        "http_seeds": metainfo.http_seeds,
+        "creation_date": metainfo.creation_date,
+        "created_by": metainfo.created_by,
+        "comment": metainfo.comment,
+    })
+}
+
+fn magnet_json(magnet: &Magnet) -> Value {
+    json!({
+        "name": magnet.display_name,
+        "info_hash": magnet.info_hash.map(|hash| HEXLOWER.encode(&hash)),
+        "info_hash_v2": magnet.info_hash_v2.map(|hash| HEXLOWER.encode(&hash)),
+        "trackers": magnet.trackers.iter().map(|url| vec![url]).collect::<Vec<_>>(),
+        "web_seeds": magnet.web_seeds,
+        "peers": magnet.peers,
+    })
+}
+
+// `announce` alone counts as a single tier.
+fn tracker_tiers(metainfo: &Metainfo) -> Vec<Vec<String>> {
+    if metainfo.announce_list.is_empty() {
+        metainfo.announce.iter().map(|url| vec![url.clone()]).collect()
+    } else {
+        metainfo.announce_list.clone()
+    }
+}
+
+fn print_metainfo(metainfo: &Metainfo) {
+    let info = &metainfo.info;
+    println!("Name:         {}", info.name);
+    if let Some(hash) = v1_hash(metainfo) {
+        println!("Info hash v1: {}", hash);
+    }
+    if let Some(hash) = metainfo.info_hash_v2() {
+        println!("Info hash v2: {}", HEXLOWER.encode(&hash));
+    }
+    println!("Version:      {}", version_name(info.version));
+    let files = files(info);
+    let total_size: u64 = files.iter().map(|(_, length)| length).sum();
+    println!("Size:         {} ({} bytes)", format_size(total_size), total_size);
+    println!("Pieces:       {} x {}", piece_count(info), format_size(info.piece_length));
+    println!("Private:      {}", if info.private { "yes" } else { "no" });
+    if let Some(date) = metainfo.creation_date {
+        println!("Created:      {}", format_date(date));
+    }
+    if let Some(created_by) = &metainfo.created_by {
+        println!("Created by:   {}", created_by);
+    }
+    if let Some(comment) = &metainfo.comment {
+        println!("Comment:      {}", comment);
+    }
+    print_trackers(&tracker_tiers(metainfo));
+    print_urls("Web seeds:", &metainfo.web_seeds);
+    print_urls("HTTP seeds:", &metainfo.http_seeds);
+
+    println!("Files:");
+    let mut directory: Vec<String> = Vec::new();
+    for (path, length) in files {
+        let (file_name, parents) = path.split_last().expect("paths are never empty");
+        let common = directory.iter().zip(parents).take_while(|(a, b)| a == b).count();
+        directory.truncate(common);
+        for parent in &parents[common..] {
+            println!("  {}{}/", "  ".repeat(directory.len()), parent);
+            directory.push(parent.clone());
+        }
+        println!("  {}{} ({})", "  ".repeat(directory.len()), file_name, format_size(length));
+    }
+}
+
+fn print_magnet(magnet: &Magnet) {
+    if let Some(name) = &magnet.display_name {
+        println!("Name:         {}", name);
+    }
+    if let Some(hash) = &magnet.info_hash {
+        println!("Info hash v1: {}", HEXLOWER.encode(hash));
+    }
+    if let Some(hash) = &magnet.info_hash_v2 {
+        println!("Info hash v2: {}", HEXLOWER.encode(hash));
+    }
+    let tiers: Vec<Vec<String>> = magnet.trackers.iter().map(|url| vec![url.clone()]).collect();
+    print_trackers(&tiers);
+    print_urls("Web seeds:", &magnet.web_seeds);
+    if !magnet.peers.is_empty() {
+        println!("Peers:");
+        for peer in &magnet.peers {
+            println!("  {}", peer);
+        }
+    }
+}
+
+fn print_trackers(tiers: &[Vec<String>]) {
+    if tiers.is_empty() {
+        return;
+    }
+    println!("Trackers:");
+    for (index, tier) in tiers.iter().enumerate() {
+        for (position, url) in tier.iter().enumerate() {
+            if position == 0 {
+                println!("  tier {}: {}", index + 1, url);
+            } else {
+                println!("  {:w$}{}", "", url, w = format!("tier {}: ", index + 1).len());
+            }
+        }
+    }
+}
+
+fn print_urls(heading: &str, urls: &[String]) {
+    if urls.is_empty() {
+        return;
+    }
+    println!("{}", heading);
+    for url in urls {
+        println!("  {}", url);
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+// Seconds since the epoch as a UTC date, using the days-to-civil algorithm
+// from Howard Hinnant's date library.
+fn format_date(timestamp: i64) -> String {
+    let days = timestamp.div_euclid(86400);
+    let seconds = timestamp.rem_euclid(86400);
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod info_tests {
+    use super::*;
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_date(-1), "1969-12-31 23:59:59 UTC");
+        assert_eq!(format_date(951782400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_date(1709164800), "2024-02-29 00:00:00 UTC");
+        // 2100 is not a leap year
+        assert_eq!(format_date(4107542399), "2100-02-28 23:59:59 UTC");
+        assert_eq!(format_date(4107542400), "2100-03-01 00:00:00 UTC");
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1024), "1.0 KiB");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(1024 * 1024), "1.0 MiB");
+        assert_eq!(format_size(1 << 50), "1.0 PiB");
+        assert_eq!(format_size(u64::MAX), "16384.0 PiB");
+    }
+
+    #[test]
+    fn test_files_skip_padding() {
+        let metainfo = Metainfo::from_bytes(
+            b"d4:infod5:filesld6:lengthi3e4:pathl1:aeed4:attr1:p6:lengthi16381e4:pathl4:.pad5:16381eed\
+              6:lengthi2e4:pathl3:sub1:beee4:name1:d12:piece lengthi16384e6:pieces40:aaaaaaaaaaaaaaaaaaaa\
+              bbbbbbbbbbbbbbbbbbbbee",
+        )
+        .unwrap();
+        assert_eq!(
+            files(&metainfo.info),
+            [
+                (vec![String::from("d"), String::from("a")], 3),
+                (vec![String::from("d"), String::from("sub"), String::from("b")], 2),
+            ]
+        );
+    }
+}
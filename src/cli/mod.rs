@@ -0,0 +1,285 @@
+use std::fs::{read, read_to_string, write};
+use std::io::Write;
+use torrent::bencode::json::{from_json, to_json, BinaryEncoding};
+use torrent::bencode::{decode_bencode, encode_bencode};
+use data_encoding::HEXLOWER;
+use torrent::metainfo::{create_torrent, edit_torrent, CreateOptions, Edit, EditError, Metainfo};
+use torrent::tracker::{HttpTrackerClient, TrackerTiers, UdpTrackerClient, MAX_TRIES};
+
+mod info;
+
+pub use info::info_command;
+
+pub const USAGE: &str = "usage:
+  torrent                                       announce test.torrent to its trackers
+  torrent bencode decode <file> [--base64]      print a bencoded file as JSON
+  torrent bencode encode <file.json> [<output>] encode JSON back to bencode
+  torrent create <path> [options]               build a .torrent from a file or directory
+      -o <output>            write to <output> instead of <path>.torrent
+      --tracker <url,...>    add a tier of trackers, repeatable
+      --web-seed <url>       add a web seed, repeatable
+      --piece-length <bytes> power of two from 16 KiB to 128 MiB, chosen from the size by default
+      --comment <text>
+      --private
+      --no-date              leave out the creation date
+  torrent info <file-or-magnet> [--json]        print a torrent's metadata
+  torrent edit <file> [options]                 change top-level fields, keeping the info-hash
+      -o <output>            write to <output> instead of overwriting <file>
+      --tracker <url,...>    replace all trackers; repeat for more tiers
+      --clear-trackers
+      --replace-tracker <old> <new>
+      --comment <text> | --clear-comment
+      --created-by <text> | --clear-created-by
+      --web-seed <url>       replace the web seeds; repeatable
+      --clear-web-seeds
+      --private | --public   changes the info-hash, needs --allow-info-change
+      --allow-info-change
+  torrent scrape <file> [--tries <n>]           ask every tracker for seeders and leechers
+      --tries <n>            give up on a UDP tracker after n tries (at most 32), 9 (about two hours) by default";
+
+pub fn bencode_command(args: &[String]) -> Result<(), String> {
+    match args {
+        [command, input, options @ ..] if command == "decode" => {
+            let binary = match options {
+                [] => BinaryEncoding::Hex,
+                [option] if option == "--hex" => BinaryEncoding::Hex,
+                [option] if option == "--base64" => BinaryEncoding::Base64,
+                _ => return Err(USAGE.to_string()),
+            };
+            let content = read(input).map_err(|e| format!("{}: {}", input, e))?;
+            let value = decode_bencode(&content).map_err(|e| format!("{}: {}", input, e))?;
+            let json = serde_json::to_string_pretty(&to_json(&value, binary)).map_err(|e| e.to_string())?;
+            println!("{}", json);
+            Ok(())
+        }
+        [command, input, output @ ..] if command == "encode" && output.len() <= 1 => {
+            let content = read_to_string(input).map_err(|e| format!("{}: {}", input, e))?;
+            let json = serde_json::from_str(&content).map_err(|e| format!("{}: {}", input, e))?;
+            let encoded = encode_bencode(&from_json(&json).map_err(|e| format!("{}: {}", input, e))?);
+            match output.first() {
+                Some(output) => write(output, encoded).map_err(|e| format!("{}: {}", output, e)),
+                None => std::io::stdout().write_all(&encoded).map_err(|e| e.to_string()),
+            }
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+pub fn create_command(args: &[String]) -> Result<(), String> {
+    let (input, mut rest) = match args {
+        [input, rest @ ..] if !input.starts_with('-') => (input, rest),
+        _ => return Err(USAGE.to_string()),
+    };
+    let mut output = format!("{}.torrent", input.trim_end_matches('/'));
+    let mut options = CreateOptions {
+        created_by: Some(format!("torrent {}", env!("CARGO_PKG_VERSION"))),
+        ..CreateOptions::default()
+    };
+    let mut creation_date = true;
+    while let Some((option, tail)) = rest.split_first() {
+        rest = tail;
+        if option == "--private" {
+            options.private = true;
+            continue;
+        }
+        if option == "--no-date" {
+            creation_date = false;
+            continue;
+        }
+        let (value, tail) = rest.split_first().ok_or_else(|| USAGE.to_string())?;
+        rest = tail;
+        match option.as_str() {
+            "-o" => output = value.clone(),
+            "--tracker" => options.trackers.push(value.split(',').map(str::to_string).collect()),
+            "--web-seed" => options.web_seeds.push(value.clone()),
+            "--comment" => options.comment = Some(value.clone()),
+            "--piece-length" => {
+                options.piece_length = Some(value.parse().map_err(|_| format!("invalid piece length {}", value))?)
+            }
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    if creation_date {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| e.to_string())?;
+        options.creation_date = Some(now.as_secs() as i64);
+    }
+
+    let torrent = create_torrent(input, &options).map_err(|e| e.to_string())?;
+    write(&output, torrent).map_err(|e| format!("{}: {}", output, e))?;
+    println!("{}", output);
+    Ok(())
+}
+
+pub fn edit_command(args: &[String]) -> Result<(), String> {
+    let (input, rest) = match args {
+        [input, rest @ ..] if !input.starts_with('-') => (input, rest),
+        _ => return Err(USAGE.to_string()),
+    };
+    let (output, edits, allow_info_change) = parse_edit_options(rest)?;
+    let output = output.unwrap_or_else(|| input.clone());
+
+    let content = read(input).map_err(|e| format!("{}: {}", input, e))?;
+    let edited = edit_torrent(&content, &edits, allow_info_change).map_err(|e| match e {
+        EditError::ChangesInfoHash(_) => format!("{}: {}; pass --allow-info-change to do it anyway", input, e),
+        e => format!("{}: {}", input, e),
+    })?;
+    let before = Metainfo::from_bytes(&content).map_err(|e| e.to_string())?;
+    let after = Metainfo::from_bytes(&edited).map_err(|e| e.to_string())?;
+    if before.info_hash() != after.info_hash() {
+        eprintln!(
+            "warning: info-hash changed from {} to {}",
+            HEXLOWER.encode(&before.info_hash()),
+            HEXLOWER.encode(&after.info_hash())
+        );
+    }
+    write(&output, edited).map_err(|e| format!("{}: {}", output, e))
+}
+
+// Edits come out in command line order. Repeated --tracker and --web-seed
+// values gather into one edit, unless another edit of the same list came
+// in between.
+fn parse_edit_options(mut rest: &[String]) -> Result<(Option<String>, Vec<Edit>, bool), String> {
+    let mut output = None;
+    let mut edits = Vec::new();
+    let mut allow_info_change = false;
+    while let Some((option, tail)) = rest.split_first() {
+        rest = tail;
+        let flag = match option.as_str() {
+            "--clear-trackers" => Some(Edit::SetTrackers(Vec::new())),
+            "--clear-comment" => Some(Edit::SetComment(None)),
+            "--clear-created-by" => Some(Edit::SetCreatedBy(None)),
+            "--clear-web-seeds" => Some(Edit::SetWebSeeds(Vec::new())),
+            "--private" => Some(Edit::SetPrivate(true)),
+            "--public" => Some(Edit::SetPrivate(false)),
+            "--allow-info-change" => {
+                allow_info_change = true;
+                continue;
+            }
+            _ => None,
+        };
+        if let Some(edit) = flag {
+            edits.push(edit);
+            continue;
+        }
+        let (value, tail) = rest.split_first().ok_or_else(|| USAGE.to_string())?;
+        rest = tail;
+        match option.as_str() {
+            "-o" => output = Some(value.clone()),
+            "--tracker" => {
+                let tier = value.split(',').map(str::to_string).collect();
+                let last = edits
+                    .iter_mut()
+                    .rev()
+                    .find(|edit| matches!(edit, Edit::SetTrackers(_) | Edit::ReplaceTracker { .. }));
+                match last {
+                    Some(Edit::SetTrackers(tiers)) => tiers.push(tier),
+                    _ => edits.push(Edit::SetTrackers(vec![tier])),
+                }
+            }
+            "--web-seed" => match edits.iter_mut().rev().find(|edit| matches!(edit, Edit::SetWebSeeds(_))) {
+                Some(Edit::SetWebSeeds(urls)) => urls.push(value.clone()),
+                _ => edits.push(Edit::SetWebSeeds(vec![value.clone()])),
+            },
+            "--comment" => edits.push(Edit::SetComment(Some(value.clone()))),
+            "--created-by" => edits.push(Edit::SetCreatedBy(Some(value.clone()))),
+            "--replace-tracker" => {
+                let (to, tail) = rest.split_first().ok_or_else(|| USAGE.to_string())?;
+                rest = tail;
+                edits.push(Edit::ReplaceTracker {
+                    from: value.clone(),
+                    to: to.clone(),
+                });
+            }
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    Ok((output, edits, allow_info_change))
+}
+
+pub fn scrape_command(args: &[String]) -> Result<(), String> {
+    let (input, tries) = match args {
+        [input] => (input, MAX_TRIES),
+        [input, option, tries] if option == "--tries" => {
+            (input, tries.parse().map_err(|_| format!("invalid number of tries {}", tries))?)
+        }
+        _ => return Err(USAGE.to_string()),
+    };
+    let metainfo = Metainfo::from_file(input).map_err(|e| format!("{}: {}", input, e))?;
+    let tiers = TrackerTiers::from_metainfo(&metainfo);
+    let info_hash = metainfo.tracker_info_hash();
+    if tiers.is_empty() {
+        return Err(format!("{}: the torrent has no trackers", input));
+    }
+
+    // trackers are asked in parallel so a dead one only costs its own timeout
+    let results: Vec<_> = std::thread::scope(|scope| {
+        let queries: Vec<_> = tiers
+            .urls()
+            .map(|url| {
+                scope.spawn(move || {
+                    let stats = if url.starts_with("http") {
+                        HttpTrackerClient::new().scrape(url, &[info_hash])
+                    } else {
+                        let mut client = UdpTrackerClient::bind().map_err(|e| e.to_string())?;
+                        client.set_max_tries(tries);
+                        client.scrape(url, &[info_hash])
+                    };
+                    let mut stats = stats.map_err(|e| e.to_string())?;
+                    stats.remove(&info_hash).ok_or_else(|| String::from("torrent not in the response"))
+                })
+            })
+            .collect();
+        let results = queries
+            .into_iter()
+            .map(|query| query.join().unwrap_or_else(|_| Err(String::from("the query panicked"))));
+        tiers.urls().zip(results).collect()
+    });
+    for (url, result) in results {
+        match result {
+            Ok(stats) => println!(
+                "{}: {} seeders, {} completed, {} leechers",
+                url, stats.seeders, stats.completed, stats.leechers
+            ),
+            Err(e) => println!("{}: {}", url, e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use super::*;
+
+    fn edits(args: &[&str]) -> Vec<Edit> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        parse_edit_options(&args).unwrap().1
+    }
+
+    fn tiers(tiers: &[&[&str]]) -> Edit {
+        Edit::SetTrackers(tiers.iter().map(|tier| tier.iter().map(|url| url.to_string()).collect()).collect())
+    }
+
+    #[test]
+    fn test_edit_order() {
+        assert_eq!(edits(&["--tracker", "a,b", "--comment", "x", "--tracker", "c"]), [
+            tiers(&[&["a", "b"], &["c"]]),
+            Edit::SetComment(Some(String::from("x"))),
+        ]);
+        assert_eq!(edits(&["--tracker", "a", "--clear-trackers"]), [tiers(&[&["a"]]), tiers(&[])]);
+        assert_eq!(edits(&["--clear-trackers", "--tracker", "a"]), [tiers(&[&["a"]])]);
+        assert_eq!(edits(&["--tracker", "c", "--replace-tracker", "c", "d"]), [
+            tiers(&[&["c"]]),
+            Edit::ReplaceTracker { from: String::from("c"), to: String::from("d") },
+        ]);
+        assert_eq!(edits(&["--replace-tracker", "c", "d", "--tracker", "e"]), [
+            Edit::ReplaceTracker { from: String::from("c"), to: String::from("d") },
+            tiers(&[&["e"]]),
+        ]);
+        assert_eq!(edits(&["--web-seed", "a", "--clear-web-seeds", "--web-seed", "b", "--web-seed", "c"]), [
+            Edit::SetWebSeeds(vec![String::from("a")]),
+            Edit::SetWebSeeds(vec![String::from("b"), String::from("c")]),
+        ]);
+    }
+}
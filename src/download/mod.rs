@@ -0,0 +1,7 @@
+mod picker;
+mod storage;
+mod webseed;
+
+pub use picker::PiecePicker;
+pub use storage::Storage;
+pub use webseed::{file_url, WebSeed, WebSeedError, WebSeedKind};
@@ -0,0 +1,135 @@
+/// Decides which piece to fetch next from which source.
+///
+/// Peers and web seeds share one picker: each source registers the pieces it
+/// has, asks for work with [`PiecePicker::pick`] and reports back whether the
+/// piece arrived intact. Rarer pieces are handed out first so that losing a
+/// source costs as little as possible.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PiecePicker {
+    have: Vec<bool>,
+    requested: Vec<bool>,
+    availability: Vec<u32>,
+}
+
+impl PiecePicker {
+    pub fn new(piece_count: usize) -> Self {
+        PiecePicker {
+            have: vec![false; piece_count],
+            requested: vec![false; piece_count],
+            availability: vec![0; piece_count],
+        }
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.have.len()
+    }
+
+    pub fn has(&self, piece: usize) -> bool {
+        self.have.get(piece).copied().unwrap_or(false)
+    }
+
+    /// Pieces not yet received.
+    pub fn remaining(&self) -> usize {
+        self.have.iter().filter(|have| !**have).count()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Counts a source with the pieces set in `pieces`, a bitfield in piece order.
+    pub fn add_source(&mut self, pieces: &[bool]) {
+        for (count, has) in self.availability.iter_mut().zip(pieces) {
+            *count += u32::from(*has);
+        }
+    }
+
+    /// Forgets a source previously passed to [`PiecePicker::add_source`].
+    pub fn remove_source(&mut self, pieces: &[bool]) {
+        for (count, has) in self.availability.iter_mut().zip(pieces) {
+            *count = count.saturating_sub(u32::from(*has));
+        }
+    }
+
+    /// Counts a source that has every piece, such as a web seed.
+    pub fn add_seed(&mut self) {
+        for count in &mut self.availability {
+            *count += 1;
+        }
+    }
+
+    /// Forgets a source previously passed to [`PiecePicker::add_seed`].
+    pub fn remove_seed(&mut self) {
+        for count in &mut self.availability {
+            *count = count.saturating_sub(1);
+        }
+    }
+
+    /// Picks the rarest piece for which `source_has` is true and that is
+    /// neither received nor requested from another source, and marks it
+    /// requested. Ties go to the lowest index.
+    pub fn pick(&mut self, source_has: impl Fn(usize) -> bool) -> Option<usize> {
+        let piece = (0..self.piece_count())
+            .filter(|&piece| !self.have[piece] && !self.requested[piece] && source_has(piece))
+            .min_by_key(|&piece| self.availability[piece])?;
+        self.requested[piece] = true;
+        Some(piece)
+    }
+
+    /// Marks `piece` as received and verified.
+    pub fn received(&mut self, piece: usize) {
+        self.have[piece] = true;
+        self.requested[piece] = false;
+    }
+
+    /// Returns `piece` to the pool after a failed or corrupt download.
+    pub fn failed(&mut self, piece: usize) {
+        self.requested[piece] = false;
+    }
+}
+
+#[cfg(test)]
+mod picker_tests {
+    use super::*;
+
+    #[test]
+    fn test_rarest_first() {
+        let mut picker = PiecePicker::new(4);
+        picker.add_seed();
+        picker.add_source(&[true, true, false, true]);
+        picker.add_source(&[true, false, false, true]);
+        assert_eq!(picker.pick(|_| true), Some(2));
+        assert_eq!(picker.pick(|_| true), Some(1));
+        assert_eq!(picker.pick(|piece| piece != 0), Some(3));
+        assert_eq!(picker.pick(|piece| piece != 0), None);
+        picker.remove_source(&[true, false, false, true]);
+        assert_eq!(picker.pick(|_| true), Some(0));
+    }
+
+    #[test]
+    fn test_unpaired_removal() {
+        let mut picker = PiecePicker::new(2);
+        picker.add_source(&[true, false]);
+        picker.remove_seed();
+        picker.remove_source(&[true, true]);
+        assert_eq!(picker.availability, [0, 0]);
+    }
+
+    #[test]
+    fn test_received_and_failed() {
+        let mut picker = PiecePicker::new(2);
+        picker.add_seed();
+        let first = picker.pick(|_| true).unwrap();
+        let second = picker.pick(|_| true).unwrap();
+        assert_eq!(picker.pick(|_| true), None);
+        picker.received(first);
+        picker.failed(second);
+        assert!(picker.has(first));
+        assert!(!picker.has(second));
+        assert_eq!(picker.remaining(), 1);
+        assert_eq!(picker.pick(|_| true), Some(second));
+        picker.received(second);
+        assert!(picker.is_complete());
+        assert_eq!(picker.pick(|_| true), None);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::metainfo::{FileLayout, Info, LayoutFile, PieceHash};
+use sha1::{Digest, Sha1};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// A torrent's files below a download directory, written and read piece by
+/// piece.
+///
+/// Padding files are never created: their part of a piece is dropped when
+/// writing and reads back as zeros. Symlinks are created by
+/// [`Storage::finish`] rather than written.
+#[derive(Debug, Clone)]
+pub struct Storage {
+    root: PathBuf,
+    layout: FileLayout,
+    pieces: Vec<PieceHash>,
+}
+
+// Windows marks a file hidden when it is created; elsewhere a hidden file is
+// one whose name starts with a dot, which the torrent already decided.
+#[cfg(windows)]
+const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+
+impl Storage {
+    pub fn new(root: impl Into<PathBuf>, info: &Info) -> Storage {
+        Storage {
+            root: root.into(),
+            layout: info.layout(),
+            pieces: info.pieces.clone(),
+        }
+    }
+
+    pub fn layout(&self) -> &FileLayout {
+        &self.layout
+    }
+
+    /// Writes piece `piece`, creating files and directories as needed.
+    /// `data` must be the whole piece.
+    pub fn write_piece(&self, piece: usize, data: &[u8]) -> io::Result<()> {
+        let size = self.piece_size(piece)?;
+        if data.len() as u64 != size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("piece {} is {} bytes, got {}", piece, size, data.len()),
+            ));
+        }
+        let mut rest = data;
+        for span in self.layout.piece_spans(piece) {
+            let (chunk, tail) = rest.split_at((span.length as usize).min(rest.len()));
+            rest = tail;
+            let file = &self.layout.files[span.file];
+            if !on_disk(file) {
+                continue;
+            }
+            let mut handle = self.open(file)?;
+            handle.seek(SeekFrom::Start(span.file_offset))?;
+            handle.write_all(chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Reads piece `piece` back from disk.
+    pub fn read_piece(&self, piece: usize) -> io::Result<Vec<u8>> {
+        self.piece_size(piece)?;
+        let mut data = Vec::new();
+        for span in self.layout.piece_spans(piece) {
+            let file = &self.layout.files[span.file];
+            let start = data.len();
+            data.resize(start + span.length as usize, 0);
+            if on_disk(file) {
+                let mut handle = File::open(self.root.join(&file.path))?;
+                handle.seek(SeekFrom::Start(span.file_offset))?;
+                handle.read_exact(&mut data[start..])?;
+            }
+        }
+        Ok(data)
+    }
+
+    /// Whether piece `piece` on disk matches its v1 hash. Missing or short
+    /// files count as a mismatch; v2-only torrents have no v1 hashes to check
+    /// against and fail with `InvalidInput`.
+    pub fn verify_piece(&self, piece: usize) -> io::Result<bool> {
+        self.piece_size(piece)?;
+        let Some(hash) = self.pieces.get(piece) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("no v1 hash for piece {}", piece)));
+        };
+        match self.read_piece(piece) {
+            Ok(data) => Ok(Sha1::digest(&data).as_slice() == hash.as_slice()),
+            Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::UnexpectedEof) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Completes the download once every piece is written: creates empty
+    /// files and symlinks, and marks executable files as such.
+    pub fn finish(&self) -> io::Result<()> {
+        for file in &self.layout.files {
+            let path = self.root.join(&file.path);
+            if let Some(target) = &file.symlink_path {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                if fs::symlink_metadata(&path).is_ok() {
+                    fs::remove_file(&path)?;
+                }
+                symlink(&relative_target(&file.path, target), &path)?;
+                continue;
+            }
+            if !on_disk(file) {
+                continue;
+            }
+            if file.length == 0 {
+                self.open(file)?;
+            }
+            if file.attributes.executable {
+                set_executable(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn piece_size(&self, piece: usize) -> io::Result<u64> {
+        let start = piece as u64 * self.layout.piece_length;
+        if start >= self.layout.total_length {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("no piece {}", piece)));
+        }
+        Ok(self.layout.piece_length.min(self.layout.total_length - start))
+    }
+
+    fn open(&self, file: &LayoutFile) -> io::Result<File> {
+        let path = self.root.join(&file.path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(false);
+        #[cfg(windows)]
+        if file.attributes.hidden {
+            std::os::windows::fs::OpenOptionsExt::attributes(&mut options, FILE_ATTRIBUTE_HIDDEN);
+        }
+        options.open(path)
+    }
+}
+
+fn on_disk(file: &LayoutFile) -> bool {
+    !file.attributes.padding && file.symlink_path.is_none()
+}
+
+// `target` as seen from the directory holding `link`; both are relative to
+// the download directory.
+fn relative_target(link: &Path, target: &Path) -> PathBuf {
+    let depth = link.parent().map_or(0, |parent| parent.components().count());
+    let mut relative: PathBuf = std::iter::repeat_n("..", depth).collect();
+    relative.push(target);
+    relative
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_target: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "symlinks are not supported on this platform"))
+}
+
+// Everyone who may read the file may also run it.
+#[cfg(unix)]
+fn set_executable(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut permissions = fs::metadata(path)?.permissions();
+    let mode = permissions.mode();
+    permissions.set_mode(mode | (mode & 0o444) >> 2);
+    fs::set_permissions(path, permissions)
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod storage_tests {
+    use super::*;
+    use crate::metainfo::{FileAttributes, FileEntry, Metainfo, TorrentVersion};
+    use tempfile::tempdir;
+
+    // Contents of tests/fixtures/hybrid.torrent in piece order.
+    fn hybrid_stream() -> Vec<u8> {
+        let mut stream: Vec<u8> = (0..100_000u32).map(|i| ((i * 31 + 7) % 256) as u8).collect();
+        stream.resize(131_072, 0);
+        stream.extend((0..1000u32).map(|i| ((i * 13) % 256) as u8));
+        stream
+    }
+
+    #[test]
+    fn test_padding_is_never_written() {
+        let metainfo = Metainfo::from_bytes(include_bytes!("../../tests/fixtures/hybrid.torrent")).unwrap();
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path(), &metainfo.info);
+        let stream = hybrid_stream();
+        for (piece, data) in stream.chunks(metainfo.info.piece_length as usize).enumerate() {
+            assert!(!storage.verify_piece(piece).unwrap());
+            storage.write_piece(piece, data).unwrap();
+        }
+        storage.finish().unwrap();
+        for piece in 0..metainfo.info.piece_count() {
+            assert!(storage.verify_piece(piece).unwrap(), "piece {}", piece);
+        }
+        let root = dir.path().join(&metainfo.info.name);
+        assert!(!root.join(".pad").exists());
+        assert_eq!(fs::read(root.join("dir/big.bin")).unwrap(), &stream[..100_000]);
+        assert_eq!(fs::read(root.join("zero")).unwrap(), b"");
+
+        fs::write(root.join("small.txt"), vec![0; 1000]).unwrap();
+        assert!(!storage.verify_piece(4).unwrap());
+        assert!(storage.verify_piece(3).unwrap());
+    }
+
+    #[test]
+    fn test_rejects_bad_pieces() {
+        let metainfo = Metainfo::from_bytes(include_bytes!("../../tests/fixtures/hybrid.torrent")).unwrap();
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path(), &metainfo.info);
+        let piece_count = metainfo.info.piece_count();
+        let invalid = |result: io::Result<()>| result.unwrap_err().kind() == io::ErrorKind::InvalidInput;
+        assert!(invalid(storage.verify_piece(piece_count).map(|_| ())));
+        assert!(invalid(storage.read_piece(piece_count).map(|_| ())));
+        assert!(invalid(storage.write_piece(piece_count, &[0; 1000])));
+        // a short piece would leave a short file behind
+        assert!(invalid(storage.write_piece(0, &[0; 100])));
+        assert!(!dir.path().join(&metainfo.info.name).exists());
+
+        let mut v2_only = metainfo.info.clone();
+        v2_only.pieces.clear();
+        v2_only.version = TorrentVersion::V2;
+        let storage = Storage::new(dir.path(), &v2_only);
+        assert!(invalid(storage.verify_piece(0).map(|_| ())));
+    }
+
+    #[test]
+    fn test_attributes() {
+        let entry = |path: &[&str], length, attr, symlink_path: Option<Vec<String>>| FileEntry {
+            path: path.iter().map(|component| component.to_string()).collect(),
+            length,
+            attributes: FileAttributes::parse(attr),
+            symlink_path,
+        };
+        let files = vec![
+            entry(&["bin", "run.sh"], 10, "x", None),
+            entry(&[".pad", "6"], 6, "p", None),
+            entry(&["run"], 0, "l", Some(vec![String::from("bin"), String::from("run.sh")])),
+            entry(&["notes"], 4, "h", None),
+        ];
+        let mut stream = b"#!/bin/sh\n".to_vec();
+        stream.extend([0; 6]);
+        stream.extend(b"todo");
+        let info = Info {
+            name: String::from("tools"),
+            piece_length: 16384,
+            pieces: vec![Sha1::digest(&stream).into()],
+            length: stream.len() as u64,
+            files: Some(files),
+            private: false,
+            version: TorrentVersion::V1,
+            file_tree: Vec::new(),
+            attributes: FileAttributes::default(),
+            symlink_path: None,
+        };
+        let dir = tempdir().unwrap();
+        let storage = Storage::new(dir.path(), &info);
+        storage.write_piece(0, &stream).unwrap();
+        storage.finish().unwrap();
+        assert!(storage.verify_piece(0).unwrap());
+        // finishing twice replaces the symlink instead of failing
+        storage.finish().unwrap();
+
+        let root = dir.path().join("tools");
+        assert!(!root.join(".pad").exists());
+        assert_eq!(fs::read(root.join("notes")).unwrap(), b"todo");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(root.join("bin/run.sh")).unwrap().permissions().mode();
+            assert_eq!(mode & 0o100, 0o100);
+            assert_eq!(fs::read_link(root.join("run")).unwrap(), PathBuf::from("../tools/bin/run.sh"));
+            assert_eq!(fs::read(root.join("run")).unwrap(), b"#!/bin/sh\n");
+        }
+
+        // a single-file symlink points below the download directory
+        let link = Info {
+            name: String::from("notes"),
+            pieces: Vec::new(),
+            length: 0,
+            files: None,
+            attributes: FileAttributes::parse("l"),
+            symlink_path: Some(vec![String::from("tools"), String::from("notes")]),
+            ..info
+        };
+        Storage::new(dir.path(), &link).finish().unwrap();
+        #[cfg(unix)]
+        {
+            assert_eq!(fs::read_link(dir.path().join("notes")).unwrap(), PathBuf::from("tools/notes"));
+            assert_eq!(fs::read(dir.path().join("notes")).unwrap(), b"todo");
+        }
+    }
+}
@@ -0,0 +1,465 @@
+use crate::download::PiecePicker;
+use crate::metainfo::{FileLayout, Info, Metainfo};
+use percent_encoding::{percent_encode, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::blocking::Client;
+use reqwest::header::RANGE;
+use sha1::{Digest, Sha1};
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+// How long a busy BEP 17 seed is left alone when it does not say.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+// Everything but RFC 3986 unreserved characters.
+const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+/// How a web seed url is asked for data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebSeedKind {
+    /// BEP 19 `url-list`: a plain HTTP server holding the torrent's files,
+    /// read with Range requests.
+    UrlList,
+    /// BEP 17 `httpseeds`: a script that returns whole pieces by index.
+    HttpSeed,
+}
+
+#[derive(Debug)]
+pub enum WebSeedError {
+    Http(reqwest::Error),
+    /// The server answered with a status other than 200 or 206.
+    Status(u16),
+    /// The server answered a Range request with the whole file. Fetching each
+    /// piece that way would download the file once per piece.
+    RangeNotSupported,
+    /// A BEP 17 seed is too busy and asks to be tried again after a while.
+    Busy(Duration),
+    /// The torrent has no piece with this index.
+    NoSuchPiece(usize),
+    WrongLength { expected: u64, got: u64 },
+    HashMismatch(usize),
+    /// The torrent is v2 only; web seeds are checked against v1 piece hashes.
+    NoPieceHashes,
+    /// The caller failed to store a piece.
+    Io(io::Error),
+}
+
+impl From<reqwest::Error> for WebSeedError {
+    fn from(e: reqwest::Error) -> Self {
+        WebSeedError::Http(e)
+    }
+}
+
+impl Display for WebSeedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebSeedError::Http(e) => write!(f, "{}", e),
+            WebSeedError::Status(status) => write!(f, "server answered with status {}", status),
+            WebSeedError::RangeNotSupported => write!(f, "server does not support range requests"),
+            WebSeedError::Busy(retry_after) => write!(f, "server busy, retry in {}s", retry_after.as_secs()),
+            WebSeedError::NoSuchPiece(piece) => write!(f, "no piece {}", piece),
+            WebSeedError::WrongLength { expected, got } => write!(f, "expected {} bytes, got {}", expected, got),
+            WebSeedError::HashMismatch(piece) => write!(f, "piece {} failed its hash check", piece),
+            WebSeedError::NoPieceHashes => write!(f, "web seeds need v1 piece hashes"),
+            WebSeedError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for WebSeedError {}
+
+/// An HTTP server that has every piece of a torrent.
+#[derive(Debug, Clone)]
+pub struct WebSeed {
+    url: String,
+    kind: WebSeedKind,
+    client: Client,
+}
+
+impl WebSeed {
+    pub fn new(url: impl Into<String>, kind: WebSeedKind) -> Self {
+        WebSeed {
+            url: url.into(),
+            kind,
+            client: Client::new(),
+        }
+    }
+
+    /// Every `url-list` and `httpseeds` entry of the torrent, in that order.
+    pub fn from_metainfo(metainfo: &Metainfo) -> Vec<WebSeed> {
+        let url_list = metainfo.web_seeds.iter().map(|url| (url, WebSeedKind::UrlList));
+        let http_seeds = metainfo.http_seeds.iter().map(|url| (url, WebSeedKind::HttpSeed));
+        url_list.chain(http_seeds).map(|(url, kind)| WebSeed::new(url.clone(), kind)).collect()
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn kind(&self) -> WebSeedKind {
+        self.kind
+    }
+
+    /// Downloads piece `piece` and checks it against its hash.
+    pub fn fetch_piece(&self, metainfo: &Metainfo, piece: usize) -> Result<Vec<u8>, WebSeedError> {
+        if metainfo.info.pieces.is_empty() {
+            return Err(WebSeedError::NoPieceHashes);
+        }
+        self.fetch(metainfo, &metainfo.info.layout(), piece)
+    }
+
+    /// Fetches the pieces `picker` hands out until it has nothing left, and
+    /// passes each verified piece to `store`. Returns the number of pieces
+    /// fetched.
+    ///
+    /// The seed counts towards availability while this runs, so peers sharing
+    /// the picker are steered towards pieces it cannot serve faster. When a
+    /// BEP 17 seed is busy the piece goes back to the picker and the seed
+    /// waits as long as it was asked to; on any other error the piece goes
+    /// back and the seed stops.
+    pub fn download(
+        &self,
+        metainfo: &Metainfo,
+        picker: &Mutex<PiecePicker>,
+        mut store: impl FnMut(usize, Vec<u8>) -> io::Result<()>,
+    ) -> Result<usize, WebSeedError> {
+        if metainfo.info.pieces.is_empty() {
+            return Err(WebSeedError::NoPieceHashes);
+        }
+        let layout = metainfo.info.layout();
+        picker.lock().unwrap().add_seed();
+        let mut fetched = 0;
+        let result = loop {
+            // bound first so the lock is released while the piece downloads
+            let next = picker.lock().unwrap().pick(|_| true);
+            let Some(piece) = next else {
+                break Ok(fetched);
+            };
+            let stored = self
+                .fetch(metainfo, &layout, piece)
+                .and_then(|data| store(piece, data).map_err(WebSeedError::Io));
+            match stored {
+                Ok(()) => {
+                    picker.lock().unwrap().received(piece);
+                    fetched += 1;
+                }
+                Err(WebSeedError::Busy(retry_after)) => {
+                    picker.lock().unwrap().failed(piece);
+                    thread::sleep(retry_after);
+                }
+                Err(e) => {
+                    picker.lock().unwrap().failed(piece);
+                    break Err(e);
+                }
+            }
+        };
+        picker.lock().unwrap().remove_seed();
+        result
+    }
+
+    fn fetch(&self, metainfo: &Metainfo, layout: &FileLayout, piece: usize) -> Result<Vec<u8>, WebSeedError> {
+        let info = &metainfo.info;
+        if piece >= info.pieces.len() {
+            return Err(WebSeedError::NoSuchPiece(piece));
+        }
+        let data = match self.kind {
+            WebSeedKind::UrlList => {
+                let mut data = Vec::new();
+                for span in layout.piece_spans(piece) {
+                    // padding is all zeros and not on the server
+                    if layout.files[span.file].attributes.padding {
+                        data.resize(data.len() + span.length as usize, 0);
+                        continue;
+                    }
+                    let url = file_url(&self.url, info, span.file);
+                    let whole_file = span.file_offset == 0 && span.length == layout.files[span.file].length;
+                    data.extend(self.fetch_range(&url, span.file_offset, span.length, whole_file)?);
+                }
+                data
+            }
+            WebSeedKind::HttpSeed => {
+                let separator = if self.url.contains('?') { '&' } else { '?' };
+                let url = format!(
+                    "{}{}info_hash={}&piece={}",
+                    self.url,
+                    separator,
+                    percent_encode(&metainfo.info_hash(), COMPONENT),
+                    piece
+                );
+                let response = self.client.get(url).send()?;
+                match response.status().as_u16() {
+                    200 => {}
+                    // the body is the number of seconds to wait
+                    503 => {
+                        let retry_after = response
+                            .text()
+                            .ok()
+                            .and_then(|body| body.trim().parse().ok())
+                            .map_or(DEFAULT_RETRY_AFTER, Duration::from_secs);
+                        return Err(WebSeedError::Busy(retry_after));
+                    }
+                    status => return Err(WebSeedError::Status(status)),
+                }
+                let data = response.bytes()?.to_vec();
+                let expected = info.piece_size(piece).unwrap_or(0);
+                if data.len() as u64 != expected {
+                    return Err(WebSeedError::WrongLength {
+                        expected,
+                        got: data.len() as u64,
+                    });
+                }
+                data
+            }
+        };
+        if Sha1::digest(&data).as_slice() != info.pieces[piece].as_slice() {
+            return Err(WebSeedError::HashMismatch(piece));
+        }
+        Ok(data)
+    }
+
+    // Servers that ignore the Range header send the whole file with a 200,
+    // which is only what was asked for when the range is `whole_file`.
+    fn fetch_range(&self, url: &str, offset: u64, length: u64, whole_file: bool) -> Result<Vec<u8>, WebSeedError> {
+        let response = self
+            .client
+            .get(url)
+            .header(RANGE, format!("bytes={}-{}", offset, offset + length - 1))
+            .send()?;
+        match response.status().as_u16() {
+            206 => {}
+            200 if whole_file => {}
+            // dropped unread rather than downloading the whole file
+            200 => return Err(WebSeedError::RangeNotSupported),
+            status => return Err(WebSeedError::Status(status)),
+        }
+        let data = response.bytes()?;
+        if data.len() as u64 != length {
+            return Err(WebSeedError::WrongLength {
+                expected: length,
+                got: data.len() as u64,
+            });
+        }
+        Ok(data.to_vec())
+    }
+}
+
+/// Url of file `file` of `info` below the BEP 19 url `base`. A url ending in
+/// '/' is a directory holding the torrent; any other url of a single-file
+/// torrent names the file itself.
+pub fn file_url(base: &str, info: &Info, file: usize) -> String {
+    let mut components = vec![info.name.as_str()];
+    match &info.files {
+        Some(files) => components.extend(files[file].path.iter().map(String::as_str)),
+        None if !base.ends_with('/') => return base.to_string(),
+        None => {}
+    }
+    let mut url = base.to_string();
+    if !url.ends_with('/') {
+        url.push('/');
+    }
+    let components: Vec<String> = components
+        .into_iter()
+        .map(|component| utf8_percent_encode(component, COMPONENT).to_string())
+        .collect();
+    url.push_str(&components.join("/"));
+    url
+}
+
+#[cfg(test)]
+mod webseed_tests {
+    use super::*;
+    use crate::metainfo::{create_torrent, CreateOptions};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tempfile::tempdir;
+    use tiny_http::{Response, Server};
+
+    type Handler = Box<dyn Fn(&str, Option<(u64, u64)>) -> (u16, Vec<u8>) + Send>;
+
+    // Serves `handler` on a local port until the test process exits and
+    // returns the server's base url.
+    fn serve(handler: Handler) -> String {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().to_ip().unwrap().port();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let range = request
+                    .headers()
+                    .iter()
+                    .find(|header| header.field.equiv("Range"))
+                    .and_then(|header| {
+                        let (start, end) = header.value.as_str().strip_prefix("bytes=")?.split_once('-')?;
+                        Some((start.parse().ok()?, end.parse().ok()?))
+                    });
+                let (status, body) = handler(request.url(), range);
+                request.respond(Response::from_data(body).with_status_code(status)).unwrap();
+            }
+        });
+        format!("http://127.0.0.1:{}", port)
+    }
+
+    fn content(length: usize, seed: u8) -> Vec<u8> {
+        (0..length).map(|i| (i as u8).wrapping_mul(seed).wrapping_add(seed)).collect()
+    }
+
+    // A three file torrent whose pieces cross file boundaries, its files by
+    // url path and all of its data in piece order.
+    fn release() -> (Metainfo, HashMap<String, Vec<u8>>, Vec<u8>) {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("release");
+        fs::create_dir_all(root.join("sub dir")).unwrap();
+        let files = [("a.bin", content(20_000, 3)), ("c.bin", content(5_000, 11)), ("sub dir/b.bin", content(30_000, 7))];
+        let mut by_url = HashMap::new();
+        let mut stream = Vec::new();
+        for (path, data) in &files {
+            fs::write(root.join(path), data).unwrap();
+            by_url.insert(format!("/release/{}", path.replace(' ', "%20")), data.clone());
+            stream.extend(data);
+        }
+        let options = CreateOptions {
+            piece_length: Some(16384),
+            ..CreateOptions::default()
+        };
+        let metainfo = Metainfo::from_bytes(&create_torrent(&root, &options).unwrap()).unwrap();
+        (metainfo, by_url, stream)
+    }
+
+    fn range_handler(files: HashMap<String, Vec<u8>>) -> Handler {
+        Box::new(move |url, range| match (files.get(url), range) {
+            (Some(data), Some((start, end))) => (206, data[start as usize..=end as usize].to_vec()),
+            (Some(data), None) => (200, data.clone()),
+            (None, _) => (404, Vec::new()),
+        })
+    }
+
+    #[test]
+    fn test_file_url() {
+        let (metainfo, _, _) = release();
+        assert_eq!(file_url("http://m.example/pub", &metainfo.info, 2), "http://m.example/pub/release/sub%20dir/b.bin");
+        assert_eq!(file_url("http://m.example/pub/", &metainfo.info, 0), "http://m.example/pub/release/a.bin");
+        let mut info = metainfo.info.clone();
+        info.files = None;
+        info.name = String::from("single file.iso");
+        assert_eq!(file_url("http://m.example/pub/", &info, 0), "http://m.example/pub/single%20file.iso");
+        assert_eq!(file_url("http://m.example/x.iso", &info, 0), "http://m.example/x.iso");
+    }
+
+    #[test]
+    fn test_seeds_share_picker() {
+        let (metainfo, files, stream) = release();
+        let piece_length = metainfo.info.piece_length as usize;
+        let pieces = stream.clone();
+        let http_seed: Handler = Box::new(move |url, _| {
+            let piece: usize = url.rsplit_once("piece=").unwrap().1.parse().unwrap();
+            let start = piece * piece_length;
+            (200, pieces[start..(start + piece_length).min(pieces.len())].to_vec())
+        });
+        let seeds = [
+            WebSeed::new(format!("{}/", serve(range_handler(files))), WebSeedKind::UrlList),
+            WebSeed::new(format!("{}/seed.php", serve(http_seed)), WebSeedKind::HttpSeed),
+        ];
+
+        let picker = Mutex::new(PiecePicker::new(metainfo.info.piece_count()));
+        let received = Mutex::new(vec![Vec::new(); metainfo.info.piece_count()]);
+        let fetched: usize = thread::scope(|scope| {
+            let workers: Vec<_> = seeds
+                .iter()
+                .map(|seed| {
+                    scope.spawn(|| {
+                        seed.download(&metainfo, &picker, |piece, data| {
+                            received.lock().unwrap()[piece] = data;
+                            Ok(())
+                        })
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|worker| worker.join().unwrap().unwrap()).sum()
+        });
+        assert_eq!(fetched, metainfo.info.piece_count());
+        assert!(picker.into_inner().unwrap().is_complete());
+        assert_eq!(received.into_inner().unwrap().concat(), stream);
+    }
+
+    fn single_file(length: usize) -> (Metainfo, Vec<u8>) {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("image.iso");
+        let data = content(length, 5);
+        fs::write(&path, &data).unwrap();
+        let options = CreateOptions {
+            piece_length: Some(16384),
+            ..CreateOptions::default()
+        };
+        (Metainfo::from_bytes(&create_torrent(&path, &options).unwrap()).unwrap(), data)
+    }
+
+    // Serves `data` at /mirror/image-v1.iso, ignoring Range headers.
+    fn serve_without_ranges(data: Vec<u8>) -> String {
+        serve(Box::new(move |url, _| match url {
+            "/mirror/image-v1.iso" => (200, data.clone()),
+            _ => (404, Vec::new()),
+        }))
+    }
+
+    #[test]
+    fn test_single_file_without_range_support() {
+        let (metainfo, data) = single_file(40_000);
+        let base = serve_without_ranges(data);
+        let seed = WebSeed::new(format!("{}/mirror/image-v1.iso", base), WebSeedKind::UrlList);
+        assert!(matches!(seed.fetch_piece(&metainfo, 2), Err(WebSeedError::RangeNotSupported)));
+        assert!(matches!(seed.fetch_piece(&metainfo, 3), Err(WebSeedError::NoSuchPiece(3))));
+
+        let missing = WebSeed::new(format!("{}/other/", base), WebSeedKind::UrlList);
+        assert!(matches!(missing.fetch_piece(&metainfo, 0), Err(WebSeedError::Status(404))));
+
+        // a range covering the whole file may be answered with all of it
+        let (metainfo, data) = single_file(10_000);
+        let base = serve_without_ranges(data.clone());
+        let seed = WebSeed::new(format!("{}/mirror/image-v1.iso", base), WebSeedKind::UrlList);
+        assert_eq!(seed.fetch_piece(&metainfo, 0).unwrap(), data);
+    }
+
+    #[test]
+    fn test_busy_http_seed_is_retried() {
+        let (metainfo, data) = single_file(40_000);
+        let piece_length = metainfo.info.piece_length as usize;
+        let requests = AtomicUsize::new(0);
+        let seed = serve(Box::new(move |url, _| {
+            // busy for the first and third request
+            if matches!(requests.fetch_add(1, Ordering::SeqCst), 0 | 2) {
+                return (503, b"0".to_vec());
+            }
+            let piece: usize = url.rsplit_once("piece=").unwrap().1.parse().unwrap();
+            let start = piece * piece_length;
+            (200, data[start..(start + piece_length).min(data.len())].to_vec())
+        }));
+        let seed = WebSeed::new(format!("{}/seed.php", seed), WebSeedKind::HttpSeed);
+        assert!(matches!(seed.fetch_piece(&metainfo, 0), Err(WebSeedError::Busy(retry_after)) if retry_after.is_zero()));
+
+        let picker = Mutex::new(PiecePicker::new(metainfo.info.piece_count()));
+        let fetched = seed.download(&metainfo, &picker, |_, _| Ok(())).unwrap();
+        assert_eq!(fetched, metainfo.info.piece_count());
+        assert!(picker.into_inner().unwrap().is_complete());
+    }
+
+    #[test]
+    fn test_corrupt_piece_returns_to_picker() {
+        let (metainfo, mut files, _) = release();
+        files.get_mut("/release/c.bin").unwrap()[0] ^= 1;
+        let seed = WebSeed::new(serve(range_handler(files)), WebSeedKind::UrlList);
+        let picker = Mutex::new(PiecePicker::new(metainfo.info.piece_count()));
+        let mut stored = Vec::new();
+        let result = seed.download(&metainfo, &picker, |piece, _| {
+            stored.push(piece);
+            Ok(())
+        });
+        // c.bin starts at byte 20000, inside piece 1
+        assert!(matches!(result, Err(WebSeedError::HashMismatch(1))));
+        assert_eq!(stored, [0]);
+        let mut picker = picker.into_inner().unwrap();
+        assert_eq!(picker.remaining(), picker.piece_count() - 1);
+        assert_eq!(picker.pick(|_| true), Some(1));
+    }
+}
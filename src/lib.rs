@@ -0,0 +1,5 @@
+pub mod bencode;
+pub mod download;
+pub mod magnet;
+pub mod metainfo;
+pub mod tracker;
@@ -0,0 +1,284 @@
+use crate::metainfo::{Metainfo, Sha256Hash, TorrentVersion};
+use data_encoding::{BASE32, HEXLOWER, HEXLOWER_PERMISSIVE};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::fmt::{Display, Formatter};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+const PREFIX: &str = "magnet:?";
+const BTIH: &str = "urn:btih:";
+const BTMH: &str = "urn:btmh:";
+// multihash header of a 32 byte SHA-256 digest
+const SHA256_MULTIHASH: &str = "1220";
+
+// RFC 3986 unreserved characters stay readable, everything else is escaped.
+const VALUE: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MagnetError {
+    NotAMagnet,
+    /// Neither a `urn:btih:` nor a `urn:btmh:` exact topic.
+    MissingInfoHash,
+    InvalidInfoHash(String),
+    InvalidSelection(String),
+    /// A parameter value that is not valid utf8 once percent-decoded.
+    InvalidEncoding(String),
+}
+
+impl Display for MagnetError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MagnetError::NotAMagnet => write!(f, "not a magnet link"),
+            MagnetError::MissingInfoHash => write!(f, "magnet link has no BitTorrent info-hash"),
+            MagnetError::InvalidInfoHash(hash) => write!(f, "invalid info-hash {}", hash),
+            MagnetError::InvalidSelection(so) => write!(f, "invalid file selection {}", so),
+            MagnetError::InvalidEncoding(param) => write!(f, "invalid encoding in parameter {}", param),
+        }
+    }
+}
+
+impl std::error::Error for MagnetError {}
+
+/// A BitTorrent magnet link (BEP 9, with BEP 52 `btmh` and BEP 53 `so`).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Magnet {
+    /// v1 info-hash from `xt=urn:btih:`, hex or base32.
+    pub info_hash: Option<[u8; 20]>,
+    /// v2 info-hash from `xt=urn:btmh:`.
+    pub info_hash_v2: Option<Sha256Hash>,
+    /// `dn`, the suggested name.
+    pub display_name: Option<String>,
+    /// `tr`, in the order given.
+    pub trackers: Vec<String>,
+    /// `ws`, web seed urls.
+    pub web_seeds: Vec<String>,
+    /// `x.pe`, peer addresses as `host:port`.
+    pub peers: Vec<String>,
+    /// `so`, indices of the files to download.
+    pub select_only: Vec<RangeInclusive<usize>>,
+}
+
+impl Magnet {
+    pub fn parse(uri: &str) -> Result<Magnet, MagnetError> {
+        let query = strip_prefix_ignore_case(uri, PREFIX).ok_or(MagnetError::NotAMagnet)?;
+
+        let mut magnet = Magnet::default();
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            let value = decode_value(value).ok_or_else(|| MagnetError::InvalidEncoding(key.to_string()))?;
+            // clients number repeated parameters as `tr.1`, `tr.2`, ...
+            let key = match key.rsplit_once('.') {
+                Some((name, index)) if !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()) => name,
+                _ => key,
+            };
+            match key {
+                "xt" => magnet.parse_exact_topic(&value)?,
+                "dn" => magnet.display_name = Some(value),
+                "tr" => magnet.trackers.push(value),
+                "ws" => magnet.web_seeds.push(value),
+                "x.pe" => magnet.peers.push(value),
+                "so" => magnet.select_only.extend(parse_selection(&value)?),
+                _ => {}
+            }
+        }
+        if magnet.info_hash.is_none() && magnet.info_hash_v2.is_none() {
+            return Err(MagnetError::MissingInfoHash);
+        }
+        Ok(magnet)
+    }
+
+    /// A magnet for a loaded torrent with its name and trackers.
+    pub fn from_metainfo(metainfo: &Metainfo) -> Magnet {
+        let mut trackers: Vec<String> = metainfo.announce.iter().cloned().collect();
+        for tracker in metainfo.announce_list.iter().flatten() {
+            if !trackers.contains(tracker) {
+                trackers.push(tracker.clone());
+            }
+        }
+        Magnet {
+            info_hash: match metainfo.info.version {
+                TorrentVersion::V2 => None,
+                _ => Some(metainfo.info_hash()),
+            },
+            info_hash_v2: metainfo.info_hash_v2(),
+            display_name: Some(metainfo.info.name.clone()),
+            trackers,
+            web_seeds: metainfo.web_seeds.clone(),
+            ..Magnet::default()
+        }
+    }
+
+    fn parse_exact_topic(&mut self, topic: &str) -> Result<(), MagnetError> {
+        let invalid = || MagnetError::InvalidInfoHash(topic.to_string());
+        if let Some(hash) = strip_prefix_ignore_case(topic, BTIH) {
+            let decoded = match hash.len() {
+                40 => HEXLOWER_PERMISSIVE.decode(hash.as_bytes()),
+                32 => BASE32.decode(hash.to_ascii_uppercase().as_bytes()),
+                _ => return Err(invalid()),
+            };
+            self.info_hash = Some(decoded.ok().and_then(|hash| hash.try_into().ok()).ok_or_else(invalid)?);
+        } else if let Some(hash) = strip_prefix_ignore_case(topic, BTMH) {
+            // only SHA-256 multihashes are defined for BitTorrent
+            let digest = hash.strip_prefix(SHA256_MULTIHASH).filter(|digest| digest.len() == 64).ok_or_else(invalid)?;
+            let decoded = HEXLOWER_PERMISSIVE.decode(digest.as_bytes()).map_err(|_| invalid())?;
+            self.info_hash_v2 = Some(decoded.try_into().map_err(|_| invalid())?);
+        }
+        // other urn namespaces belong to other protocols
+        Ok(())
+    }
+}
+
+impl FromStr for Magnet {
+    type Err = MagnetError;
+
+    fn from_str(uri: &str) -> Result<Self, Self::Err> {
+        Magnet::parse(uri)
+    }
+}
+
+impl Display for Magnet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut params = Vec::new();
+        if let Some(hash) = &self.info_hash {
+            params.push(format!("xt={}{}", BTIH, HEXLOWER.encode(hash)));
+        }
+        if let Some(hash) = &self.info_hash_v2 {
+            params.push(format!("xt={}{}{}", BTMH, SHA256_MULTIHASH, HEXLOWER.encode(hash)));
+        }
+        if let Some(name) = &self.display_name {
+            params.push(format!("dn={}", utf8_percent_encode(name, VALUE)));
+        }
+        for (key, values) in [("tr", &self.trackers), ("ws", &self.web_seeds), ("x.pe", &self.peers)] {
+            for value in values {
+                params.push(format!("{}={}", key, utf8_percent_encode(value, VALUE)));
+            }
+        }
+        if !self.select_only.is_empty() {
+            let ranges: Vec<String> = self
+                .select_only
+                .iter()
+                .map(|range| {
+                    if range.start() == range.end() {
+                        range.start().to_string()
+                    } else {
+                        format!("{}-{}", range.start(), range.end())
+                    }
+                })
+                .collect();
+            params.push(format!("so={}", ranges.join(",")));
+        }
+        write!(f, "{}{}", PREFIX, params.join("&"))
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(value: &'a str, prefix: &str) -> Option<&'a str> {
+    value
+        .get(..prefix.len())
+        .filter(|start| start.eq_ignore_ascii_case(prefix))
+        .map(|_| &value[prefix.len()..])
+}
+
+// Query values may encode spaces as `+` as well as `%20`.
+fn decode_value(value: &str) -> Option<String> {
+    let value = value.replace('+', " ");
+    percent_decode_str(&value).decode_utf8().ok().map(|value| value.into_owned())
+}
+
+fn parse_selection(selection: &str) -> Result<Vec<RangeInclusive<usize>>, MagnetError> {
+    let invalid = || MagnetError::InvalidSelection(selection.to_string());
+    selection
+        .split(',')
+        .map(|item| {
+            let (start, end) = item.split_once('-').unwrap_or((item, item));
+            let start: usize = start.parse().map_err(|_| invalid())?;
+            let end: usize = end.parse().map_err(|_| invalid())?;
+            if start > end {
+                return Err(invalid());
+            }
+            Ok(start..=end)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod magnet_tests {
+    use super::*;
+
+    const HASH: &str = "c12fe1c06bba254a9dc9f519b335aa7c1367a88a";
+
+    #[test]
+    fn test_parse() {
+        let magnet = Magnet::parse(
+            "magnet:?xt=urn:btih:C12FE1C06BBA254A9DC9F519B335AA7C1367A88A&dn=Some+File%20name.iso\
+             &tr=udp%3A%2F%2Ftracker.example.org%3A6969&tr.1=http://t2.example.org/announce\
+             &ws=https%3A%2F%2Fmirror.example.org%2F&x.pe=10.0.0.1:6881&x.pe.1=[::1]:6881&so=0,2,4-6&foo=bar\
+             &tr.=http://unnumbered.example.org/announce",
+        )
+        .unwrap();
+        assert_eq!(HEXLOWER.encode(&magnet.info_hash.unwrap()), HASH);
+        assert_eq!(magnet.info_hash_v2, None);
+        assert_eq!(magnet.display_name.as_deref(), Some("Some File name.iso"));
+        assert_eq!(magnet.trackers, ["udp://tracker.example.org:6969", "http://t2.example.org/announce"]);
+        assert_eq!(magnet.web_seeds, ["https://mirror.example.org/"]);
+        assert_eq!(magnet.peers, ["10.0.0.1:6881", "[::1]:6881"]);
+        assert_eq!(magnet.select_only, [0..=0, 2..=2, 4..=6]);
+    }
+
+    #[test]
+    fn test_base32_and_v2() {
+        let magnet = Magnet::parse("magnet:?xt=urn:btih:YEX6DQDLXISUVHOJ6UM3GNNKPQJWPKEK").unwrap();
+        assert_eq!(HEXLOWER.encode(&magnet.info_hash.unwrap()), HASH);
+
+        let v2 = "d76bd62f20fb9db2c41fe6ec9a310bc36c7a10beb1c4b972b9020f1e680c8cfa";
+        let magnet: Magnet = format!("magnet:?xt=urn:btmh:1220{}&xt=urn:ed2k:abc", v2).parse().unwrap();
+        assert_eq!(magnet.info_hash, None);
+        assert_eq!(HEXLOWER.encode(&magnet.info_hash_v2.unwrap()), v2);
+    }
+
+    #[test]
+    fn test_render_round_trip() {
+        let uri = format!(
+            "magnet:?xt=urn:btih:{}&dn=a%20b%26c&tr=udp%3A%2F%2Ft.example.org%3A1337%2Fannounce\
+             &ws=http%3A%2F%2Fm.example.org%2F&x.pe=1.2.3.4%3A5&so=1%2C3-4",
+            HASH
+        );
+        let magnet = Magnet::parse(&uri).unwrap();
+        assert_eq!(magnet.display_name.as_deref(), Some("a b&c"));
+        let rendered = magnet.to_string();
+        assert!(rendered.ends_with("&so=1,3-4"));
+        assert_eq!(Magnet::parse(&rendered).unwrap(), magnet);
+    }
+
+    #[test]
+    fn test_from_metainfo() {
+        let metainfo = Metainfo::from_bytes(include_bytes!("../../tests/fixtures/multi_file.torrent")).unwrap();
+        let magnet = Magnet::from_metainfo(&metainfo);
+        assert_eq!(magnet.info_hash, Some(metainfo.info_hash()));
+        assert_eq!(magnet.display_name.as_deref(), Some("multi"));
+        assert_eq!(magnet.trackers.len(), 3);
+        assert_eq!(Magnet::parse(&magnet.to_string()).unwrap(), magnet);
+
+        let metainfo = Metainfo::from_bytes(include_bytes!("../../tests/fixtures/hybrid.torrent")).unwrap();
+        let rendered = Magnet::from_metainfo(&metainfo).to_string();
+        assert!(rendered.starts_with("magnet:?xt=urn:btih:e5637b714bdd958e9fb56944969a48e8f8019d70&xt=urn:btmh:1220"));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(Magnet::parse("http://example.org"), Err(MagnetError::NotAMagnet));
+        assert_eq!(Magnet::parse("magnet:?dn=x"), Err(MagnetError::MissingInfoHash));
+        assert_eq!(
+            Magnet::parse("magnet:?xt=urn:btih:abcd"),
+            Err(MagnetError::InvalidInfoHash(String::from("urn:btih:abcd")))
+        );
+        assert!(Magnet::parse(&format!("magnet:?xt=urn:btmh:1114{}", "00".repeat(32))).is_err());
+        assert_eq!(
+            Magnet::parse(&format!("magnet:?xt=urn:btih:{}&so=3-1", HASH)),
+            Err(MagnetError::InvalidSelection(String::from("3-1")))
+        );
+        assert_eq!(
+            Magnet::parse(&format!("magnet:?xt=urn:btih:{}&dn=%ff", HASH)),
+            Err(MagnetError::InvalidEncoding(String::from("dn")))
+        );
+    }
+}
@@ -1,99 +1,62 @@
-use crate::bencode::{encode_bencode, parse_bencode, BDict, Bencode};
-use crate::tracker::{announce, connect};
-use sha1::{Digest, Sha1};
-use std::fs::read;
-use std::net::{UdpSocket};
+use torrent::metainfo::Metainfo;
+use torrent::tracker::types::AnnounceRequest;
+use torrent::tracker::{HttpTrackerClient, TrackerTiers, UdpTrackerClient};
 
-mod bencode;
-mod str_utils;
-mod tracker;
+mod cli;
 
-fn get_announce_list(info_dict: &BDict) -> Vec<String> {
-    let mut announce_list: Vec<String> = Vec::new();
-    let announce_url = info_dict
-        .get("announce")
-        .unwrap_or_else(|| panic!("No announce in file"));
-    if let Bencode::Str(announce_url) = announce_url {
-        announce_list.push(String::from_utf8(announce_url.to_vec()).unwrap())
-    }
-    let announce_list_data = info_dict.get("announce-list");
-    if announce_list_data.is_some() {
-        let announce_list_data = announce_list_data.unwrap();
-        if let Bencode::List(announce_list_data) = announce_list_data {
-            for announce_url in announce_list_data {
-                if let Bencode::List(announce_url) = announce_url {
-                    for announce_url in announce_url {
-                        if let Bencode::Str(announce_url) = announce_url {
-                            announce_list.push(String::from_utf8(announce_url.to_vec()).unwrap())
-                        }
-                    }
-                }
-            }
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        None => {
+            announce_test_torrent();
+            Ok(())
         }
+        Some("bencode") => cli::bencode_command(&args[1..]),
+        Some("create") => cli::create_command(&args[1..]),
+        Some("info") => cli::info_command(&args[1..]),
+        Some("edit") => cli::edit_command(&args[1..]),
+        Some("scrape") => cli::scrape_command(&args[1..]),
+        Some(_) => Err(cli::USAGE.to_string()),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
     }
-    announce_list
 }
-fn get_info_hash(info_dict: &BDict) -> Vec<u8> {
-    let mut hasher = Sha1::new();
-    let encoded = encode_bencode(&Bencode::Dict(info_dict.clone()));
-    hasher.update(&encoded);
-    hasher.finalize().as_slice().to_vec()
-}
-
-fn main() {
-    let content = read("test.torrent").unwrap();
-    let parsed = parse_bencode(&content);
-
-    if parsed.is_err() {
-        panic!("Invalid torrent file")
-    }
 
-    let file_data = parsed.unwrap().data;
-    let (announce_list, info_hash) = match file_data {
-        Bencode::Dict(info_dict) => {
-            let announce_list = get_announce_list(&info_dict);
-            let info_dict = info_dict.get("info").expect("No info in file");
-            if let Bencode::Dict(info_dict) = info_dict {
-                (announce_list, get_info_hash(info_dict))
-            } else {
-                panic!("Invalid torrent file")
-            }
-        }
-        _ => {
-            panic!("Invalid torrent file")
-        }
+fn announce_test_torrent() {
+    let metainfo = match Metainfo::from_file("test.torrent") {
+        Ok(metainfo) => metainfo,
+        Err(e) => panic!("Invalid torrent file: {}", e),
     };
-    let mut announce_response_list = Vec::new();
+    let mut tiers = TrackerTiers::from_metainfo(&metainfo);
+    let info_hash = metainfo.tracker_info_hash().to_vec();
 
-    let socket_v4 = UdpSocket::bind("0.0.0.0:0").unwrap();
-    let socket_v6 = UdpSocket::bind("[::]:0").unwrap();
+    let mut udp_client = UdpTrackerClient::bind().unwrap();
+    let http_client = HttpTrackerClient::new();
 
-    for announce_url in announce_list {
+    // BEP 12: stop at the first tracker that answers
+    let announce_result = tiers.announce(|announce_url| {
         println!("URL: {}", announce_url);
-        let connect_response = connect(&announce_url, &socket_v4, &socket_v6);
-        if connect_response.is_err() {
-            println!("CONNECT ERROR");
-            println!("----");
-            continue;
-        }
-        println!("CONNECT SUCCESS");
-        let connection_response = connect_response.unwrap();
-        let announce_response = announce(
-            announce_url,
-            connection_response.connection_id,
-            info_hash.clone(),
-            &socket_v4,
-            &socket_v6,
-        );
-        if announce_response.is_err() {
-            println!("ANNOUNCE ERROR");
-            println!("----");
-            continue;
+        let response = if announce_url.starts_with("http") {
+            http_client.announce(announce_url, &AnnounceRequest::new(&0, info_hash.clone()), None)
+        } else {
+            udp_client.announce(announce_url, info_hash.clone())
+        };
+        match response {
+            Ok(response) => {
+                println!("ANNOUNCE SUCCESS");
+                println!("-----");
+                Ok(response)
+            }
+            Err(e) => {
+                println!("ANNOUNCE ERROR: {}", e);
+                println!("----");
+                Err(e)
+            }
         }
-        println!("ANNOUNCE SUCCESS");
-        announce_response_list.push(announce_response.unwrap());
-        println!("-----");
-    }
+    });
+    let announce_response_list: Vec<_> = announce_result.into_iter().flatten().map(|(_, response)| response).collect();
 
     // let mut peers = HashSet::new();
     // for announce_response in announce_response_list {
@@ -0,0 +1,455 @@
+use crate::bencode::{encode_bencode, BDict, BString, Bencode};
+use crate::metainfo::edit::set_trackers;
+use crate::metainfo::{validate_path_component, PIECE_HASH_LEN};
+use sha1::{Digest, Sha1};
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+const MIN_PIECE_LENGTH: u64 = 16 * 1024;
+const MAX_PIECE_LENGTH: u64 = 16 * 1024 * 1024;
+// Every hashing thread buffers a whole piece, so explicit piece lengths are capped too.
+const MAX_EXPLICIT_PIECE_LENGTH: u64 = 128 * 1024 * 1024;
+// Automatic piece lengths aim for about this many pieces.
+const TARGET_PIECES: u64 = 1500;
+
+#[derive(Debug)]
+pub enum CreateError {
+    Io(PathBuf, io::Error),
+    /// The path holds no files with data to share.
+    Empty(PathBuf),
+    /// A file name that cannot be stored in a torrent.
+    InvalidPath(PathBuf, String),
+    InvalidPieceLength(u64),
+}
+
+impl Display for CreateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CreateError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            CreateError::Empty(path) => write!(f, "{}: no files to add", path.display()),
+            CreateError::InvalidPath(path, reason) => write!(f, "{}: {}", path.display(), reason),
+            CreateError::InvalidPieceLength(length) => {
+                write!(f, "piece length {} is not a power of two between 16 KiB and 128 MiB", length)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CreateError {}
+
+/// Everything about a new torrent except its contents.
+#[derive(Debug, Clone, Default)]
+pub struct CreateOptions {
+    /// Chosen from the total size when `None`.
+    pub piece_length: Option<u64>,
+    /// Tracker tiers. The first url becomes `announce`.
+    pub trackers: Vec<Vec<String>>,
+    pub web_seeds: Vec<String>,
+    pub comment: Option<String>,
+    pub created_by: Option<String>,
+    /// Seconds since the epoch. Left out of the torrent when `None`.
+    pub creation_date: Option<i64>,
+    pub private: bool,
+    /// Hashing threads, one per CPU when `None`.
+    pub threads: Option<usize>,
+}
+
+struct SourceFile {
+    path: PathBuf,
+    // components below the torrent root, empty for a single file torrent
+    components: Vec<String>,
+    length: u64,
+}
+
+/// Piece length for `total_length` bytes: a power of two giving roughly
+/// 1500 pieces, between 16 KiB and 16 MiB.
+pub fn default_piece_length(total_length: u64) -> u64 {
+    (total_length / TARGET_PIECES)
+        .next_power_of_two()
+        .clamp(MIN_PIECE_LENGTH, MAX_PIECE_LENGTH)
+}
+
+/// Builds a v1 .torrent for the file or directory at `path` and returns its
+/// bencoded bytes. Directory entries are added in sorted order; symlinks
+/// inside a directory are skipped and other special files are rejected.
+pub fn create_torrent(path: impl AsRef<Path>, options: &CreateOptions) -> Result<Vec<u8>, CreateError> {
+    let path = path.as_ref();
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| CreateError::InvalidPath(path.to_path_buf(), String::from("no utf8 file name")))?
+        .to_string();
+    validate_path_component(&name).map_err(|reason| CreateError::InvalidPath(path.to_path_buf(), reason))?;
+
+    let metadata = fs::metadata(path).map_err(|e| CreateError::Io(path.to_path_buf(), e))?;
+    let is_dir = metadata.is_dir();
+    if !is_dir && !metadata.is_file() {
+        return Err(not_a_regular_file(path));
+    }
+    let mut files = Vec::new();
+    if is_dir {
+        collect_files(path, &mut Vec::new(), &mut files)?;
+    } else {
+        files.push(SourceFile {
+            path: path.to_path_buf(),
+            components: Vec::new(),
+            length: metadata.len(),
+        });
+    }
+    // a torrent needs at least one piece
+    let total_length: u64 = files.iter().map(|file| file.length).sum();
+    if total_length == 0 {
+        return Err(CreateError::Empty(path.to_path_buf()));
+    }
+
+    let piece_length = options.piece_length.unwrap_or_else(|| default_piece_length(total_length));
+    if !piece_length.is_power_of_two() || !(MIN_PIECE_LENGTH..=MAX_EXPLICIT_PIECE_LENGTH).contains(&piece_length) {
+        return Err(CreateError::InvalidPieceLength(piece_length));
+    }
+    let threads = options
+        .threads
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |threads| threads.get()));
+    let pieces = hash_pieces(&files, piece_length, threads)?;
+
+    let mut info = BDict::new();
+    info.insert(key("name"), Bencode::new_str(name));
+    info.insert(key("piece length"), Bencode::Int(piece_length.into()));
+    info.insert(key("pieces"), Bencode::Str(pieces));
+    if is_dir {
+        let files = files
+            .iter()
+            .map(|file| {
+                let mut entry = BDict::new();
+                entry.insert(key("length"), Bencode::Int(file.length.into()));
+                entry.insert(
+                    key("path"),
+                    Bencode::List(file.components.iter().map(|c| Bencode::new_str(c.as_str())).collect()),
+                );
+                Bencode::Dict(entry)
+            })
+            .collect();
+        info.insert(key("files"), Bencode::List(files));
+    } else {
+        info.insert(key("length"), Bencode::Int(total_length.into()));
+    }
+    if options.private {
+        info.insert(key("private"), Bencode::Int(1));
+    }
+
+    let mut torrent = BDict::new();
+    set_trackers(&mut torrent, &options.trackers);
+    if !options.web_seeds.is_empty() {
+        let urls = options.web_seeds.iter().map(|url| Bencode::new_str(url.as_str())).collect();
+        torrent.insert(key("url-list"), Bencode::List(urls));
+    }
+    if let Some(comment) = &options.comment {
+        torrent.insert(key("comment"), Bencode::new_str(comment.as_str()));
+    }
+    if let Some(created_by) = &options.created_by {
+        torrent.insert(key("created by"), Bencode::new_str(created_by.as_str()));
+    }
+    if let Some(date) = options.creation_date {
+        torrent.insert(key("creation date"), Bencode::Int(date.into()));
+    }
+    torrent.insert(key("info"), Bencode::Dict(info));
+    Ok(encode_bencode(&Bencode::Dict(torrent)))
+}
+
+fn not_a_regular_file(path: &Path) -> CreateError {
+    CreateError::InvalidPath(path.to_path_buf(), String::from("not a regular file"))
+}
+
+fn key(key: &str) -> BString {
+    key.as_bytes().to_vec()
+}
+
+fn collect_files(dir: &Path, components: &mut Vec<String>, files: &mut Vec<SourceFile>) -> Result<(), CreateError> {
+    let io_error = |e| CreateError::Io(dir.to_path_buf(), e);
+    let mut entries = fs::read_dir(dir)
+        .map_err(io_error)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(io_error)?;
+    entries.sort();
+    for path in entries {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| CreateError::InvalidPath(path.clone(), String::from("no utf8 file name")))?
+            .to_string();
+        validate_path_component(&name).map_err(|reason| CreateError::InvalidPath(path.clone(), reason))?;
+        // symlink_metadata so a link can neither leave the tree nor loop back into it
+        let metadata = fs::symlink_metadata(&path).map_err(|e| CreateError::Io(path.clone(), e))?;
+        if metadata.file_type().is_symlink() {
+            continue;
+        }
+        components.push(name);
+        if metadata.is_dir() {
+            collect_files(&path, components, files)?;
+        } else if !metadata.is_file() {
+            return Err(not_a_regular_file(&path));
+        } else {
+            files.push(SourceFile {
+                path,
+                components: components.clone(),
+                length: metadata.len(),
+            });
+        }
+        components.pop();
+    }
+    Ok(())
+}
+
+// Splits the pieces into one contiguous run per thread; each thread reads
+// its run sequentially across file boundaries.
+fn hash_pieces(files: &[SourceFile], piece_length: u64, threads: usize) -> Result<Vec<u8>, CreateError> {
+    let total_length: u64 = files.iter().map(|file| file.length).sum();
+    let piece_count = total_length.div_ceil(piece_length);
+    let per_thread = piece_count.div_ceil(threads.max(1) as u64).max(1);
+
+    let results: Vec<Result<Vec<u8>, CreateError>> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..piece_count)
+            .step_by(per_thread as usize)
+            .map(|first| {
+                let last = (first + per_thread).min(piece_count);
+                scope.spawn(move || hash_run(files, piece_length, first..last, total_length))
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("hashing thread panicked"))
+            .collect()
+    });
+    let mut pieces = Vec::with_capacity(piece_count as usize * PIECE_HASH_LEN);
+    for run in results {
+        pieces.extend(run?);
+    }
+    Ok(pieces)
+}
+
+fn hash_run(
+    files: &[SourceFile],
+    piece_length: u64,
+    run: std::ops::Range<u64>,
+    total_length: u64,
+) -> Result<Vec<u8>, CreateError> {
+    let mut reader = StreamReader::new(files, run.start * piece_length);
+    let mut buffer = vec![0; piece_length as usize];
+    let mut hashes = Vec::with_capacity((run.end - run.start) as usize * PIECE_HASH_LEN);
+    for piece in run {
+        let size = piece_length.min(total_length - piece * piece_length) as usize;
+        reader.read_exact(&mut buffer[..size])?;
+        hashes.extend_from_slice(&Sha1::digest(&buffer[..size]));
+    }
+    Ok(hashes)
+}
+
+// Reads the files one after another as a single stream.
+struct StreamReader<'a> {
+    files: &'a [SourceFile],
+    index: usize,
+    offset: u64,
+    current: Option<io::Take<File>>,
+}
+
+impl<'a> StreamReader<'a> {
+    fn new(files: &'a [SourceFile], mut offset: u64) -> Self {
+        let mut index = 0;
+        while index < files.len() && offset >= files[index].length {
+            offset -= files[index].length;
+            index += 1;
+        }
+        StreamReader {
+            files,
+            index,
+            offset,
+            current: None,
+        }
+    }
+
+    fn read_exact(&mut self, mut buffer: &mut [u8]) -> Result<(), CreateError> {
+        while !buffer.is_empty() {
+            let file = self.files.get(self.index).ok_or_else(|| {
+                let path = self.files.last().map(|file| file.path.clone()).unwrap_or_default();
+                CreateError::Io(path, io::ErrorKind::UnexpectedEof.into())
+            })?;
+            let io_error = |e| CreateError::Io(file.path.clone(), e);
+            if self.current.is_none() {
+                let mut opened = File::open(&file.path).map_err(io_error)?;
+                io::Seek::seek(&mut opened, io::SeekFrom::Start(self.offset)).map_err(io_error)?;
+                self.current = Some(opened.take(file.length - self.offset));
+            }
+            let read = self.current.as_mut().unwrap().read(buffer).map_err(io_error)?;
+            if read == 0 {
+                // files that shrank since they were listed end up here
+                if self.current.as_ref().unwrap().limit() > 0 {
+                    return Err(io_error(io::ErrorKind::UnexpectedEof.into()));
+                }
+                self.current = None;
+                self.index += 1;
+                self.offset = 0;
+                continue;
+            }
+            buffer = &mut buffer[read..];
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod create_tests {
+    use super::*;
+    use crate::metainfo::{FileEntry, Metainfo};
+    use tempfile::tempdir;
+
+    fn content(length: usize, seed: u8) -> Vec<u8> {
+        (0..length).map(|i| (i as u8).wrapping_mul(seed).wrapping_add(seed)).collect()
+    }
+
+    #[test]
+    fn test_single_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        let data = content(100_000, 7);
+        fs::write(&path, &data).unwrap();
+        let options = CreateOptions {
+            piece_length: Some(32768),
+            trackers: vec![vec![String::from("udp://t.example.org:1337/announce")]],
+            comment: Some(String::from("hello")),
+            creation_date: Some(1700000000),
+            private: true,
+            ..CreateOptions::default()
+        };
+        let bytes = create_torrent(&path, &options).unwrap();
+        let metainfo = Metainfo::from_bytes(&bytes).unwrap();
+        assert_eq!(metainfo.announce.as_deref(), Some("udp://t.example.org:1337/announce"));
+        assert!(metainfo.announce_list.is_empty());
+        assert_eq!(metainfo.comment.as_deref(), Some("hello"));
+        assert_eq!(metainfo.creation_date, Some(1700000000));
+        assert_eq!(metainfo.info.name, "data.bin");
+        assert_eq!(metainfo.info.length, 100_000);
+        assert!(metainfo.info.private);
+        assert!(metainfo.info.files.is_none());
+        for (index, chunk) in data.chunks(32768).enumerate() {
+            assert_eq!(metainfo.info.pieces[index].as_slice(), Sha1::digest(chunk).as_slice());
+        }
+        assert_eq!(crate::bencode::decode_bencode_strict(&bytes).map(|_| ()), Ok(()));
+    }
+
+    #[test]
+    fn test_directory_matches_any_thread_count() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("release");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("b.bin"), content(70_000, 3)).unwrap();
+        fs::write(root.join("docs/a.txt"), content(1_000, 5)).unwrap();
+        fs::write(root.join("empty"), b"").unwrap();
+        fs::write(root.join("a.bin"), content(40_000, 11)).unwrap();
+
+        let options = CreateOptions {
+            piece_length: Some(16384),
+            trackers: vec![
+                vec![String::from("http://a.example.org/announce"), String::from("http://b.example.org/announce")],
+                vec![String::from("udp://c.example.org:80")],
+            ],
+            web_seeds: vec![String::from("https://mirror.example.org/")],
+            threads: Some(1),
+            ..CreateOptions::default()
+        };
+        let single = create_torrent(&root, &options).unwrap();
+        for threads in [2, 3, 64] {
+            let options = CreateOptions {
+                threads: Some(threads),
+                ..options.clone()
+            };
+            assert_eq!(create_torrent(&root, &options).unwrap(), single);
+        }
+
+        let metainfo = Metainfo::from_bytes(&single).unwrap();
+        assert_eq!(metainfo.announce_list.len(), 2);
+        assert_eq!(metainfo.info.length, 111_000);
+        assert_eq!(
+            metainfo.info.files.unwrap(),
+            [
+                FileEntry { path: vec![String::from("a.bin")], length: 40_000, ..FileEntry::default() },
+                FileEntry { path: vec![String::from("b.bin")], length: 70_000, ..FileEntry::default() },
+                FileEntry { path: vec![String::from("docs"), String::from("a.txt")], length: 1_000, ..FileEntry::default() },
+                FileEntry { path: vec![String::from("empty")], length: 0, ..FileEntry::default() },
+            ]
+        );
+        // the third piece straddles a.bin and b.bin
+        let mut stream = content(40_000, 11);
+        stream.extend(content(70_000, 3));
+        assert_eq!(metainfo.info.pieces[2].as_slice(), Sha1::digest(&stream[32768..49152]).as_slice());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_special_files() {
+        use std::os::unix::fs::symlink;
+        use std::os::unix::net::UnixListener;
+
+        let dir = tempdir().unwrap();
+        let outside = dir.path().join("outside.bin");
+        fs::write(&outside, content(1_000, 9)).unwrap();
+        let root = dir.path().join("tree");
+        fs::create_dir(&root).unwrap();
+        fs::write(root.join("data.bin"), content(1_000, 3)).unwrap();
+        symlink(".", root.join("loop")).unwrap();
+        symlink(&outside, root.join("escape")).unwrap();
+        let metainfo = Metainfo::from_bytes(&create_torrent(&root, &CreateOptions::default()).unwrap()).unwrap();
+        assert_eq!(
+            metainfo.info.files.unwrap(),
+            [FileEntry { path: vec![String::from("data.bin")], length: 1_000, ..FileEntry::default() }]
+        );
+
+        let _listener = UnixListener::bind(root.join("socket")).unwrap();
+        assert!(matches!(
+            create_torrent(&root, &CreateOptions::default()),
+            Err(CreateError::InvalidPath(path, _)) if path == root.join("socket")
+        ));
+        assert!(matches!(
+            create_torrent(root.join("socket"), &CreateOptions::default()),
+            Err(CreateError::InvalidPath(..))
+        ));
+    }
+
+    #[test]
+    fn test_piece_length() {
+        assert_eq!(default_piece_length(0), 16 * 1024);
+        assert_eq!(default_piece_length(1500 * 1024 * 1024), 1024 * 1024);
+        assert_eq!(default_piece_length(u64::MAX / 2), 16 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_errors() {
+        let dir = tempdir().unwrap();
+        let empty = dir.path().join("empty");
+        fs::create_dir(&empty).unwrap();
+        assert!(matches!(create_torrent(&empty, &CreateOptions::default()), Err(CreateError::Empty(_))));
+        fs::write(empty.join("zero"), b"").unwrap();
+        assert!(matches!(create_torrent(&empty, &CreateOptions::default()), Err(CreateError::Empty(_))));
+        assert!(matches!(
+            create_torrent(empty.join("zero"), &CreateOptions::default()),
+            Err(CreateError::Empty(_))
+        ));
+        fs::write(empty.join("x"), b"x").unwrap();
+        let options = CreateOptions {
+            piece_length: Some(1000),
+            ..CreateOptions::default()
+        };
+        assert!(matches!(create_torrent(&empty, &options), Err(CreateError::InvalidPieceLength(1000))));
+        let options = CreateOptions {
+            piece_length: Some(1 << 40),
+            ..CreateOptions::default()
+        };
+        assert!(matches!(create_torrent(&empty, &options), Err(CreateError::InvalidPieceLength(_))));
+        assert!(matches!(
+            create_torrent(dir.path().join("missing"), &CreateOptions::default()),
+            Err(CreateError::Io(..))
+        ));
+    }
+}
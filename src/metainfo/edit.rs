@@ -0,0 +1,284 @@
+use crate::bencode::{decode_bencode_ref, encode_bencode, BDict, BString, Bencode, BencodeRef, Encoder};
+use crate::metainfo::{Metainfo, MetainfoError};
+use std::fmt::{Display, Formatter};
+use std::io;
+
+/// A change to a torrent file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edit {
+    /// Replaces `announce` and `announce-list`. No tiers removes both.
+    SetTrackers(Vec<Vec<String>>),
+    /// Swaps one tracker url for another wherever it appears.
+    ReplaceTracker { from: String, to: String },
+    SetComment(Option<String>),
+    SetCreatedBy(Option<String>),
+    /// Replaces `url-list`. An empty list removes it.
+    SetWebSeeds(Vec<String>),
+    /// Sets the `private` flag. This lives in the info dictionary and so
+    /// changes the info-hash.
+    SetPrivate(bool),
+}
+
+impl Edit {
+    // Whether the edit changes the info dictionary of a torrent whose
+    // `private` flag is `private`.
+    fn changes_info(&self, private: bool) -> bool {
+        matches!(self, Edit::SetPrivate(value) if *value != private)
+    }
+}
+
+#[derive(Debug)]
+pub enum EditError {
+    /// The input is not a valid torrent.
+    Metainfo(MetainfoError),
+    /// The edit touches the info dictionary and was not allowed to.
+    ChangesInfoHash(Edit),
+    TrackerNotFound(String),
+}
+
+impl From<MetainfoError> for EditError {
+    fn from(e: MetainfoError) -> Self {
+        EditError::Metainfo(e)
+    }
+}
+
+impl Display for EditError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EditError::Metainfo(e) => write!(f, "{}", e),
+            EditError::ChangesInfoHash(Edit::SetPrivate(true)) => {
+                write!(f, "making the torrent private would change the info-hash")
+            }
+            EditError::ChangesInfoHash(Edit::SetPrivate(false)) => {
+                write!(f, "making the torrent public would change the info-hash")
+            }
+            EditError::ChangesInfoHash(edit) => write!(f, "{:?} would change the info-hash", edit),
+            EditError::TrackerNotFound(url) => write!(f, "tracker {} is not in the torrent", url),
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+/// Applies `edits` to a .torrent file and returns the new file.
+///
+/// Unless an edit changes the info dictionary, its bytes are copied from the
+/// input untouched, so the info-hash stays the same even for torrents that
+/// were not canonically encoded. Edits to the info dictionary fail with
+/// [`EditError::ChangesInfoHash`] unless `allow_info_change` is set; setting
+/// `private` to the value it already has is not one of them.
+pub fn edit_torrent(bytes: &[u8], edits: &[Edit], allow_info_change: bool) -> Result<Vec<u8>, EditError> {
+    let mut private = Metainfo::from_bytes(bytes)?.info.private;
+    if !allow_info_change {
+        if let Some(edit) = edits.iter().find(|edit| edit.changes_info(private)) {
+            return Err(EditError::ChangesInfoHash(edit.clone()));
+        }
+    }
+    let root = match decode_bencode_ref(bytes).map_err(MetainfoError::from)? {
+        BencodeRef::Dict(root) => root,
+        _ => unreachable!("checked by Metainfo::from_bytes"),
+    };
+    let info_ref = root.get("info").expect("checked by Metainfo::from_bytes");
+    let mut info_bytes = match info_ref {
+        BencodeRef::Dict(info) => info.raw.to_vec(),
+        _ => unreachable!("checked by Metainfo::from_bytes"),
+    };
+    let mut torrent = match Bencode::from(&BencodeRef::Dict(root)) {
+        Bencode::Dict(torrent) => torrent,
+        _ => unreachable!(),
+    };
+    let mut info = torrent.remove(b"info".as_slice()).expect("checked by Metainfo::from_bytes");
+
+    for edit in edits {
+        match edit {
+            Edit::SetTrackers(tiers) => set_trackers(&mut torrent, tiers),
+            Edit::ReplaceTracker { from, to } => replace_tracker(&mut torrent, from, to)?,
+            Edit::SetComment(comment) => set_optional_str(&mut torrent, "comment", comment.as_deref()),
+            Edit::SetCreatedBy(created_by) => set_optional_str(&mut torrent, "created by", created_by.as_deref()),
+            Edit::SetWebSeeds(urls) => {
+                if urls.is_empty() {
+                    torrent.remove(b"url-list".as_slice());
+                } else {
+                    torrent.insert(key("url-list"), str_list(urls));
+                }
+            }
+            // re-encoding a non-canonical info dictionary would change the
+            // info-hash even when the flag stays the same
+            Edit::SetPrivate(value) if *value == private => {}
+            Edit::SetPrivate(value) => {
+                private = *value;
+                let info = info.as_dict_mut().expect("checked by Metainfo::from_bytes");
+                if private {
+                    info.insert(key("private"), Bencode::Int(1));
+                } else {
+                    info.remove(b"private".as_slice());
+                }
+                info_bytes = encode_bencode(&Bencode::Dict(info.clone()));
+            }
+        }
+    }
+
+    let edited = write_torrent(&torrent, &info_bytes).expect("writing to a Vec cannot fail");
+    Metainfo::from_bytes(&edited)?;
+    Ok(edited)
+}
+
+// Encodes `torrent` with `info_bytes` spliced in verbatim under "info".
+fn write_torrent(torrent: &BDict, info_bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = Encoder::new(Vec::new());
+    encoder.begin_dict()?;
+    let mut info_written = false;
+    for (name, value) in torrent {
+        if !info_written && name.as_slice() > b"info".as_slice() {
+            encoder.key(b"info")?;
+            encoder.raw(info_bytes)?;
+            info_written = true;
+        }
+        encoder.key(name)?;
+        encoder.value(value)?;
+    }
+    if !info_written {
+        encoder.key(b"info")?;
+        encoder.raw(info_bytes)?;
+    }
+    encoder.end()?;
+    encoder.finish()
+}
+
+fn key(key: &str) -> BString {
+    key.as_bytes().to_vec()
+}
+
+fn str_list(strings: &[String]) -> Bencode {
+    Bencode::List(strings.iter().map(|string| Bencode::new_str(string.as_str())).collect())
+}
+
+fn set_optional_str(torrent: &mut BDict, name: &str, value: Option<&str>) {
+    match value {
+        Some(value) => torrent.insert(key(name), Bencode::new_str(value)),
+        None => torrent.remove(name.as_bytes()),
+    };
+}
+
+pub(crate) fn set_trackers(torrent: &mut BDict, tiers: &[Vec<String>]) {
+    torrent.remove(b"announce".as_slice());
+    torrent.remove(b"announce-list".as_slice());
+    let tiers: Vec<&Vec<String>> = tiers.iter().filter(|tier| !tier.is_empty()).collect();
+    if let Some(first) = tiers.first() {
+        torrent.insert(key("announce"), Bencode::new_str(first[0].as_str()));
+    }
+    // a single tracker needs no announce-list
+    if tiers.iter().map(|tier| tier.len()).sum::<usize>() > 1 {
+        torrent.insert(key("announce-list"), Bencode::List(tiers.into_iter().map(|tier| str_list(tier)).collect()));
+    }
+}
+
+fn replace_tracker(torrent: &mut BDict, from: &str, to: &str) -> Result<(), EditError> {
+    let mut found = false;
+    let mut replace = |url: &mut Bencode| {
+        if url.as_bytes() == Some(from.as_bytes()) {
+            *url = Bencode::new_str(to);
+            found = true;
+        }
+    };
+    if let Some(announce) = torrent.get_mut(b"announce".as_slice()) {
+        replace(announce);
+    }
+    let tiers = torrent.get_mut(b"announce-list".as_slice()).and_then(Bencode::as_list_mut);
+    for tier in tiers.into_iter().flatten() {
+        for url in tier.as_list_mut().into_iter().flatten() {
+            replace(url);
+        }
+    }
+    if found {
+        Ok(())
+    } else {
+        Err(EditError::TrackerNotFound(from.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod edit_tests {
+    use super::*;
+
+    fn urls(urls: &[&str]) -> Vec<String> {
+        urls.iter().map(|url| url.to_string()).collect()
+    }
+
+    #[test]
+    fn test_keeps_info_bytes() {
+        // the info dictionary of this fixture is not in canonical order
+        let input = include_bytes!("../../tests/fixtures/unsorted_info.torrent");
+        let original = Metainfo::from_bytes(input).unwrap();
+        let edits = [
+            Edit::SetTrackers(vec![urls(&["udp://a.example.org:1"]), urls(&["http://b.example.org/announce"])]),
+            Edit::SetComment(Some(String::from("edited"))),
+            Edit::SetCreatedBy(Some(String::from("torrent"))),
+            Edit::SetWebSeeds(urls(&["https://mirror.example.org/"])),
+            // already public, so nothing to change
+            Edit::SetPrivate(false),
+        ];
+        let edited = Metainfo::from_bytes(&edit_torrent(input, &edits, false).unwrap()).unwrap();
+        assert_eq!(edited.info_bytes(), original.info_bytes());
+        assert_eq!(edited.info_hash(), original.info_hash());
+        assert_eq!(edited.announce.as_deref(), Some("udp://a.example.org:1"));
+        assert_eq!(edited.announce_list.len(), 2);
+        assert_eq!(edited.comment.as_deref(), Some("edited"));
+        assert_eq!(edited.created_by.as_deref(), Some("torrent"));
+        assert_eq!(edited.web_seeds, urls(&["https://mirror.example.org/"]));
+
+        let input = include_bytes!("../../tests/fixtures/private_webseed.torrent");
+        let edited = edit_torrent(input, &[Edit::SetPrivate(true)], false).unwrap();
+        assert_eq!(edited, input);
+    }
+
+    #[test]
+    fn test_replace_and_remove() {
+        let input = include_bytes!("../../tests/fixtures/multi_file.torrent");
+        let edits = [
+            Edit::ReplaceTracker {
+                from: String::from("http://tracker.example.org/announce"),
+                to: String::from("https://new.example.org/announce"),
+            },
+            Edit::SetCreatedBy(None),
+        ];
+        let edited = Metainfo::from_bytes(&edit_torrent(input, &edits, false).unwrap()).unwrap();
+        assert_eq!(edited.announce.as_deref(), Some("https://new.example.org/announce"));
+        assert_eq!(edited.announce_list[0], urls(&["https://new.example.org/announce", "http://backup.example.org/announce"]));
+        assert_eq!(edited.created_by, None);
+        assert_eq!(edited.creation_date, Some(1690000000));
+
+        let edited = Metainfo::from_bytes(&edit_torrent(input, &[Edit::SetTrackers(Vec::new())], false).unwrap()).unwrap();
+        assert_eq!(edited.announce, None);
+        assert!(edited.announce_list.is_empty());
+    }
+
+    #[test]
+    fn test_private_changes_info_hash() {
+        let input = include_bytes!("../../tests/fixtures/single_file.torrent");
+        let edit = [Edit::SetPrivate(true)];
+        assert!(matches!(
+            edit_torrent(input, &edit, false),
+            Err(EditError::ChangesInfoHash(Edit::SetPrivate(true)))
+        ));
+        let edited = Metainfo::from_bytes(&edit_torrent(input, &edit, true).unwrap()).unwrap();
+        let original = Metainfo::from_bytes(input).unwrap();
+        assert!(edited.info.private);
+        assert_ne!(edited.info_hash(), original.info_hash());
+        assert_eq!(edited.info.pieces, original.info.pieces);
+    }
+
+    #[test]
+    fn test_errors() {
+        let input = include_bytes!("../../tests/fixtures/single_file.torrent");
+        let edit = [Edit::ReplaceTracker {
+            from: String::from("udp://missing"),
+            to: String::from("udp://other"),
+        }];
+        assert_eq!(
+            edit_torrent(input, &edit, false).unwrap_err().to_string(),
+            "tracker udp://missing is not in the torrent"
+        );
+        assert!(matches!(edit_torrent(b"de", &[], false), Err(EditError::Metainfo(_))));
+    }
+}
@@ -0,0 +1,414 @@
+use crate::bencode::{BDict, Bencode};
+use crate::metainfo::{get_bytes, get_str, get_u64, invalid, require, Info, MetainfoError};
+use std::path::PathBuf;
+
+/// BEP 47 file attributes, the letters of a file's `attr` string.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileAttributes {
+    /// `p`: filler that aligns the next file to a piece boundary. Its data
+    /// is all zeros and it is never written to disk.
+    pub padding: bool,
+    /// `x`
+    pub executable: bool,
+    /// `h`
+    pub hidden: bool,
+    /// `l`: the file is a symlink to its `symlink path`.
+    pub symlink: bool,
+}
+
+impl FileAttributes {
+    /// Reads an `attr` string. Unknown letters are ignored, as BEP 47 asks.
+    pub fn parse(attr: &str) -> FileAttributes {
+        FileAttributes {
+            padding: attr.contains('p'),
+            executable: attr.contains('x'),
+            hidden: attr.contains('h'),
+            symlink: attr.contains('l'),
+        }
+    }
+}
+
+/// One entry of a multi-file torrent's `files` list.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileEntry {
+    /// Path components below the torrent's directory, already validated.
+    pub path: Vec<String>,
+    pub length: u64,
+    pub attributes: FileAttributes,
+    /// Target of a symlink as path components below the torrent's directory.
+    pub symlink_path: Option<Vec<String>>,
+}
+
+/// A file placed in the torrent's byte stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutFile {
+    /// Path relative to the download directory, including the torrent name.
+    pub path: PathBuf,
+    pub offset: u64,
+    pub length: u64,
+    pub attributes: FileAttributes,
+    /// Symlink target relative to the download directory, like `path`.
+    pub symlink_path: Option<PathBuf>,
+}
+
+/// Part of a piece that falls inside a single file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileSpan {
+    /// Index into [`FileLayout::files`].
+    pub file: usize,
+    pub file_offset: u64,
+    pub length: u64,
+}
+
+/// Files of a torrent laid end to end, as pieces see them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileLayout {
+    pub files: Vec<LayoutFile>,
+    pub piece_length: u64,
+    pub total_length: u64,
+}
+
+impl FileLayout {
+    pub fn new(info: &Info) -> FileLayout {
+        let mut files = Vec::new();
+        let mut offset = 0;
+        match &info.files {
+            None => files.push(LayoutFile {
+                path: PathBuf::from(&info.name),
+                offset: 0,
+                length: info.length,
+                attributes: info.attributes,
+                symlink_path: info.symlink_path.as_ref().map(PathBuf::from_iter),
+            }),
+            Some(entries) => {
+                for entry in entries {
+                    let below_name = |components: &Vec<String>| {
+                        let mut path = PathBuf::from(&info.name);
+                        path.extend(components);
+                        path
+                    };
+                    files.push(LayoutFile {
+                        path: below_name(&entry.path),
+                        offset,
+                        length: entry.length,
+                        attributes: entry.attributes,
+                        symlink_path: entry.symlink_path.as_ref().map(below_name),
+                    });
+                    offset += entry.length;
+                }
+            }
+        }
+        FileLayout {
+            files,
+            piece_length: info.piece_length,
+            total_length: info.length,
+        }
+    }
+
+    /// Spans covered by `length` bytes starting at `offset` within piece
+    /// `piece`, in file order. The range is cut short at the end of the
+    /// torrent, and empty files never appear.
+    pub fn spans(&self, piece: usize, offset: u64, length: u64) -> Vec<FileSpan> {
+        let start = (piece as u64)
+            .saturating_mul(self.piece_length)
+            .saturating_add(offset)
+            .min(self.total_length);
+        let end = start.saturating_add(length).min(self.total_length);
+        if start == end {
+            return Vec::new();
+        }
+        let first = self.files.partition_point(|file| file.offset + file.length <= start);
+
+        let mut spans = Vec::new();
+        for (index, file) in self.files.iter().enumerate().skip(first) {
+            if file.offset >= end {
+                break;
+            }
+            if file.length == 0 {
+                continue;
+            }
+            let span_start = start.max(file.offset);
+            let span_end = end.min(file.offset + file.length);
+            spans.push(FileSpan {
+                file: index,
+                file_offset: span_start - file.offset,
+                length: span_end - span_start,
+            });
+        }
+        spans
+    }
+
+    /// Spans covering the whole of piece `piece`.
+    pub fn piece_spans(&self, piece: usize) -> Vec<FileSpan> {
+        self.spans(piece, 0, self.piece_length)
+    }
+}
+
+// Names Windows refuses to create, with or without an extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Checks that `component` names a single entry inside the download
+/// directory: no traversal, separators, drive prefixes or reserved names.
+pub fn validate_path_component(component: &str) -> Result<(), String> {
+    if component.is_empty() {
+        return Err(String::from("empty path component"));
+    }
+    if component == "." || component == ".." {
+        return Err(format!("path component {:?} leaves the torrent directory", component));
+    }
+    if let Some(c) = component.chars().find(|c| matches!(c, '/' | '\\' | ':' | '\0')) {
+        return Err(format!("path component {:?} contains {:?}", component, c));
+    }
+    let stem = component.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED_NAMES.iter().any(|name| name.eq_ignore_ascii_case(stem)) {
+        return Err(format!("path component {:?} is a reserved name", component));
+    }
+    Ok(())
+}
+
+pub(crate) fn parse_files(files: &Bencode) -> Result<Vec<FileEntry>, MetainfoError> {
+    let files = files.as_list().ok_or_else(|| MetainfoError::WrongType {
+        field: String::from("info.files"),
+        expected: "a list of dictionaries",
+    })?;
+    files
+        .iter()
+        .enumerate()
+        .map(|(index, file)| {
+            let prefix = format!("info.files[{}].", index);
+            let file = file.as_dict().ok_or_else(|| MetainfoError::WrongType {
+                field: format!("info.files[{}]", index),
+                expected: "a dictionary",
+            })?;
+            let (attributes, symlink_path) = parse_attributes(file, &prefix)?;
+            Ok(FileEntry {
+                length: require(get_u64(file, &prefix, "length")?, &prefix, "length")?,
+                path: parse_path(file, &prefix)?,
+                attributes,
+                symlink_path,
+            })
+        })
+        .collect()
+}
+
+/// Reads `attr` and, for symlinks, `symlink path` from a `files` entry, a
+/// v2 file tree entry or the info dictionary of a single-file torrent.
+pub(crate) fn parse_attributes(
+    file: &BDict,
+    prefix: &str,
+) -> Result<(FileAttributes, Option<Vec<String>>), MetainfoError> {
+    let attributes = FileAttributes::parse(&get_str(file, prefix, "attr")?.unwrap_or_default());
+    if !attributes.symlink {
+        return Ok((attributes, None));
+    }
+    let target = require(file.get(b"symlink path".as_slice()), prefix, "symlink path")?;
+    let target = parse_components(target, &format!("{}symlink path", prefix), true)?;
+    Ok((attributes, Some(target)))
+}
+
+fn parse_path(file: &BDict, prefix: &str) -> Result<Vec<String>, MetainfoError> {
+    let key = if file.contains_key(b"path.utf-8".as_slice()) {
+        "path.utf-8"
+    } else {
+        "path"
+    };
+    let components = require(file.get(key.as_bytes()), prefix, key)?;
+    parse_components(components, &format!("{}{}", prefix, key), key == "path.utf-8")
+}
+
+// A non-empty list of valid path components. Without `utf8` invalid bytes
+// are replaced rather than rejected.
+fn parse_components(components: &Bencode, field: &str, utf8: bool) -> Result<Vec<String>, MetainfoError> {
+    let components = components
+        .as_list()
+        .ok_or_else(|| MetainfoError::WrongType {
+            field: field.to_string(),
+            expected: "a list of strings",
+        })?;
+    if components.is_empty() {
+        return Err(invalid(field, "must not be empty"));
+    }
+    components
+        .iter()
+        .map(|component| {
+            let component = match component.as_bytes() {
+                Some(bytes) if utf8 => {
+                    String::from_utf8(bytes.to_vec()).map_err(|_| invalid(field, "not valid utf8"))?
+                }
+                Some(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+                None => {
+                    return Err(MetainfoError::WrongType {
+                        field: field.to_string(),
+                        expected: "a list of strings",
+                    })
+                }
+            };
+            validate_path_component(&component).map_err(|reason| invalid(field, reason))?;
+            Ok(component)
+        })
+        .collect()
+}
+
+// `name` is the directory in multi-file mode and the file itself otherwise,
+// so it is held to the same rules as a path component.
+pub(crate) fn parse_name(info: &BDict) -> Result<String, MetainfoError> {
+    let name = match get_str(info, "info.", "name.utf-8")? {
+        Some(name) => name,
+        None => String::from_utf8_lossy(require(get_bytes(info, "info.", "name")?, "info.", "name")?)
+            .into_owned(),
+    };
+    validate_path_component(&name).map_err(|reason| invalid("info.name", reason))?;
+    Ok(name)
+}
+
+#[cfg(test)]
+mod files_tests {
+    use super::*;
+    use crate::metainfo::{Metainfo, TorrentVersion};
+
+    fn info(files: &[u64], piece_length: u64) -> Info {
+        let length = files.iter().sum::<u64>();
+        Info {
+            name: String::from("dir"),
+            piece_length,
+            pieces: vec![[0; 20]; length.div_ceil(piece_length) as usize],
+            length,
+            files: Some(
+                files
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &length)| FileEntry {
+                        path: vec![format!("f{}", i)],
+                        length,
+                        ..FileEntry::default()
+                    })
+                    .collect(),
+            ),
+            private: false,
+            version: TorrentVersion::V1,
+            file_tree: Vec::new(),
+            attributes: FileAttributes::default(),
+            symlink_path: None,
+        }
+    }
+
+    fn span(file: usize, file_offset: u64, length: u64) -> FileSpan {
+        FileSpan {
+            file,
+            file_offset,
+            length,
+        }
+    }
+
+    #[test]
+    fn test_multi_file() {
+        let metainfo = Metainfo::from_bytes(include_bytes!("../../tests/fixtures/multi_file.torrent")).unwrap();
+        let files = metainfo.info.files.as_ref().unwrap();
+        assert_eq!(metainfo.info.length, 76200);
+        assert_eq!(files.len(), 4);
+        assert_eq!(files[1].path, ["docs", "manual.pdf"]);
+        assert_eq!(files[3].length, 0);
+
+        let layout = metainfo.info.layout();
+        assert_eq!(layout.files[2].path, PathBuf::from("multi/docs/img/logo.png"));
+        assert_eq!(layout.files[2].offset, 71200);
+        assert_eq!(layout.piece_spans(0), [span(0, 0, 1200), span(1, 0, 15184)]);
+        assert_eq!(layout.piece_spans(4), [span(1, 64 * 1024 - 1200, 70000 - (64 * 1024 - 1200)), span(2, 0, 5000)]);
+    }
+
+    #[test]
+    fn test_single_file_layout() {
+        let metainfo = Metainfo::from_bytes(include_bytes!("../../tests/fixtures/single_file.torrent")).unwrap();
+        let layout = metainfo.info.layout();
+        assert_eq!(layout.files.len(), 1);
+        assert_eq!(layout.files[0].path, PathBuf::from("single.bin"));
+        assert_eq!(layout.piece_spans(3), [span(0, 3 * 32768, 100000 - 3 * 32768)]);
+    }
+
+    #[test]
+    fn test_spans() {
+        let layout = info(&[10, 0, 5, 20], 8).layout();
+        assert_eq!(layout.piece_spans(0), [span(0, 0, 8)]);
+        assert_eq!(layout.piece_spans(1), [span(0, 8, 2), span(2, 0, 5), span(3, 0, 1)]);
+        assert_eq!(layout.spans(1, 2, 3), [span(2, 0, 3)]);
+        assert_eq!(layout.piece_spans(4), [span(3, 17, 3)]);
+        assert_eq!(layout.spans(3, 7, 100), [span(3, 16, 4)]);
+        assert!(layout.piece_spans(5).is_empty());
+        assert!(layout.spans(0, 3, 0).is_empty());
+    }
+
+    #[test]
+    fn test_attributes() {
+        assert_eq!(
+            FileAttributes::parse("xhq"),
+            FileAttributes {
+                executable: true,
+                hidden: true,
+                ..FileAttributes::default()
+            }
+        );
+        let metainfo = Metainfo::from_bytes(
+            b"d4:infod5:filesld4:attr1:x6:lengthi1e4:pathl3:runeed4:attr1:l6:lengthi0e4:pathl4:linke\
+              12:symlink pathl3:runeee4:name1:d12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaaee",
+        )
+        .unwrap();
+        let files = metainfo.info.files.as_ref().unwrap();
+        assert!(files[0].attributes.executable);
+        assert_eq!(files[0].symlink_path, None);
+        assert!(files[1].attributes.symlink);
+        assert_eq!(files[1].symlink_path, Some(vec![String::from("run")]));
+        assert_eq!(metainfo.info.layout().files[1].symlink_path, Some(PathBuf::from("d/run")));
+        let metainfo = Metainfo::from_bytes(
+            b"d4:infod4:attr1:l6:lengthi0e4:name4:link12:piece lengthi16384e6:pieces0:12:symlink pathl1:d3:runeee",
+        )
+        .unwrap();
+        assert!(metainfo.info.attributes.symlink);
+        assert_eq!(metainfo.info.symlink_path, Some(vec![String::from("d"), String::from("run")]));
+        assert_eq!(metainfo.info.layout().files[0].symlink_path, Some(PathBuf::from("d/run")));
+
+        let error = |input: &[u8]| Metainfo::from_bytes(input).unwrap_err().to_string();
+        assert_eq!(
+            error(b"d4:infod5:filesld4:attr1:l6:lengthi0e4:pathl4:linkeee4:name1:d12:piece lengthi1e6:pieces0:ee"),
+            "missing field info.files[0].symlink path"
+        );
+        assert_eq!(
+            error(b"d4:infod4:attr1:l6:lengthi0e4:name1:a12:piece lengthi1e12:symlink pathl2:..e6:pieces0:ee"),
+            "invalid info.symlink path: path component \"..\" leaves the torrent directory"
+        );
+    }
+
+    #[test]
+    fn test_path_validation() {
+        assert!(validate_path_component("readme.txt").is_ok());
+        assert!(validate_path_component("..hidden").is_ok());
+        assert!(validate_path_component("console.log").is_ok());
+        for bad in ["", ".", "..", "a/b", "a\\b", "C:", "nul", "CON.txt", "Lpt1.tar.gz", "a\0"] {
+            assert!(validate_path_component(bad).is_err(), "{:?} accepted", bad);
+        }
+
+        let error = |input: &[u8]| Metainfo::from_bytes(input).unwrap_err().to_string();
+        assert_eq!(
+            error(b"d4:infod5:filesld6:lengthi1e4:pathl2:..1:aeee4:name1:d12:piece lengthi1e6:pieces20:aaaaaaaaaaaaaaaaaaaaee"),
+            "invalid info.files[0].path: path component \"..\" leaves the torrent directory"
+        );
+        assert_eq!(
+            error(b"d4:infod5:filesld6:lengthi1e4:pathleee4:name1:d12:piece lengthi1e6:pieces20:aaaaaaaaaaaaaaaaaaaaee"),
+            "invalid info.files[0].path: must not be empty"
+        );
+        assert_eq!(
+            error(b"d4:infod5:filesld4:pathl1:aeee4:name1:d12:piece lengthi1e6:pieces0:ee"),
+            "missing field info.files[0].length"
+        );
+        assert_eq!(
+            error(b"d4:infod6:lengthi0e4:name4:/etc12:piece lengthi1e6:pieces0:ee"),
+            "invalid info.name: path component \"/etc\" contains '/'"
+        );
+        assert_eq!(
+            error(b"d4:infod5:filesle6:lengthi0e4:name1:d12:piece lengthi1e6:pieces0:ee"),
+            "invalid info: must have exactly one of length and files"
+        );
+    }
+}
@@ -0,0 +1,530 @@
+use crate::bencode::{decode_bencode_ref, BDict, BInt, Bencode, BencodeError, BencodeRef};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::io::{self, Read};
+use std::path::Path;
+
+mod create;
+mod edit;
+mod files;
+mod v2;
+
+pub use create::{create_torrent, default_piece_length, CreateError, CreateOptions};
+pub use edit::{edit_torrent, Edit, EditError};
+pub use files::{validate_path_component, FileAttributes, FileEntry, FileLayout, FileSpan, LayoutFile};
+pub use v2::{hash_file, merkle_root, root_from_piece_layer, FileHashes, Sha256Hash, V2File, BLOCK_SIZE};
+
+pub const PIECE_HASH_LEN: usize = 20;
+
+pub type PieceHash = [u8; PIECE_HASH_LEN];
+
+#[derive(Debug)]
+pub enum MetainfoError {
+    Io(io::Error),
+    Bencode(BencodeError),
+    /// The file or the `info` value is not a dictionary.
+    NotADict { field: String },
+    MissingField { field: String },
+    WrongType { field: String, expected: &'static str },
+    InvalidValue { field: String, reason: String },
+}
+
+impl From<io::Error> for MetainfoError {
+    fn from(e: io::Error) -> Self {
+        MetainfoError::Io(e)
+    }
+}
+
+impl From<BencodeError> for MetainfoError {
+    fn from(e: BencodeError) -> Self {
+        MetainfoError::Bencode(e)
+    }
+}
+
+impl Display for MetainfoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MetainfoError::Io(e) => write!(f, "cannot read torrent: {}", e),
+            MetainfoError::Bencode(e) => write!(f, "invalid bencode: {}", e),
+            MetainfoError::NotADict { field } => write!(f, "{} is not a dictionary", field),
+            MetainfoError::MissingField { field } => write!(f, "missing field {}", field),
+            MetainfoError::WrongType { field, expected } => {
+                write!(f, "{} should be {}", field, expected)
+            }
+            MetainfoError::InvalidValue { field, reason } => write!(f, "invalid {}: {}", field, reason),
+        }
+    }
+}
+
+impl std::error::Error for MetainfoError {}
+
+/// Which BitTorrent versions a torrent's info dictionary describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TorrentVersion {
+    V1,
+    /// BEP 52 only: a `file tree` and no `pieces`.
+    V2,
+    /// Both v1 `pieces` and a v2 `file tree` over the same data.
+    Hybrid,
+}
+
+/// The `info` dictionary of a torrent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Info {
+    /// Suggested file name. `name.utf-8` is preferred when present, otherwise
+    /// `name` is decoded as utf8 with invalid bytes replaced.
+    pub name: String,
+    pub piece_length: u64,
+    /// v1 piece hashes, empty for a v2-only torrent.
+    pub pieces: Vec<PieceHash>,
+    /// Total size of the torrent, summed over `files` in multi-file mode.
+    pub length: u64,
+    /// The `files` list of a multi-file torrent, `None` for a single file.
+    /// Derived from the file tree for v2-only torrents.
+    pub files: Option<Vec<FileEntry>>,
+    pub private: bool,
+    pub version: TorrentVersion,
+    /// Files of the v2 `file tree` in tree order, empty for v1 torrents.
+    pub file_tree: Vec<V2File>,
+    /// BEP 47 attributes of a single-file torrent's file.
+    pub attributes: FileAttributes,
+    /// Target of a single-file torrent's symlink as path components below
+    /// the download directory.
+    pub symlink_path: Option<Vec<String>>,
+}
+
+impl Info {
+    pub fn piece_count(&self) -> usize {
+        self.pieces.len()
+    }
+
+    /// Size of the piece at `index`; only the last piece may be shorter.
+    pub fn piece_size(&self, index: usize) -> Option<u64> {
+        if index >= self.pieces.len() {
+            return None;
+        }
+        let start = index as u64 * self.piece_length;
+        Some(self.piece_length.min(self.length - start))
+    }
+
+    /// Placement of files in the v1 piece stream.
+    pub fn layout(&self) -> FileLayout {
+        FileLayout::new(self)
+    }
+
+    /// Checks `reader` against the `pieces root` of a v2 file.
+    pub fn verify_file(&self, file: &V2File, reader: impl Read) -> io::Result<bool> {
+        Ok(hash_file(reader, self.piece_length)?.pieces_root == file.pieces_root)
+    }
+}
+
+/// A parsed .torrent file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metainfo {
+    pub announce: Option<String>,
+    pub announce_list: Vec<Vec<String>>,
+    pub info: Info,
+    pub creation_date: Option<i64>,
+    pub comment: Option<String>,
+    pub created_by: Option<String>,
+    pub encoding: Option<String>,
+    /// BEP 19 `url-list`.
+    pub web_seeds: Vec<String>,
+    /// BEP 17 `httpseeds`.
+    pub http_seeds: Vec<String>,
+    /// v2 piece hashes of each file longer than a piece, keyed by its `pieces root`.
+    pub piece_layers: BTreeMap<Sha256Hash, Vec<Sha256Hash>>,
+    // the info dictionary exactly as it appeared in the file
+    info_bytes: Vec<u8>,
+}
+
+impl Metainfo {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Metainfo, MetainfoError> {
+        let root = match decode_bencode_ref(bytes)? {
+            BencodeRef::Dict(root) => root,
+            _ => return Err(MetainfoError::NotADict { field: String::from("torrent") }),
+        };
+        let info_bytes = match root.get("info") {
+            Some(BencodeRef::Dict(info)) => info.raw.to_vec(),
+            Some(_) => return Err(MetainfoError::NotADict { field: String::from("info") }),
+            None => return Err(MetainfoError::MissingField { field: String::from("info") }),
+        };
+        let dict = match Bencode::from(&BencodeRef::Dict(root)) {
+            Bencode::Dict(dict) => dict,
+            _ => unreachable!(),
+        };
+        let info = parse_info(dict.get(b"info".as_slice()).and_then(Bencode::as_dict).unwrap())?;
+        let piece_layers = v2::parse_piece_layers(
+            dict.get(b"piece layers".as_slice()),
+            &info.file_tree,
+            info.piece_length,
+        )?;
+
+        Ok(Metainfo {
+            announce: get_str(&dict, "", "announce")?,
+            announce_list: parse_announce_list(&dict)?,
+            info,
+            creation_date: get_int(&dict, "", "creation date")?
+                .map(|date| i64::try_from(date).map_err(|_| invalid("creation date", "out of range")))
+                .transpose()?,
+            comment: get_text(&dict, "", "comment")?,
+            created_by: get_text(&dict, "", "created by")?,
+            encoding: get_text(&dict, "", "encoding")?,
+            web_seeds: parse_url_list(&dict, "url-list")?,
+            http_seeds: parse_url_list(&dict, "httpseeds")?,
+            piece_layers,
+            info_bytes,
+        })
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Metainfo, MetainfoError> {
+        Metainfo::from_bytes(&std::fs::read(path)?)
+    }
+
+    /// The bencoded info dictionary as it appeared in the source file.
+    pub fn info_bytes(&self) -> &[u8] {
+        &self.info_bytes
+    }
+
+    /// SHA-1 of the info dictionary, the v1 info-hash. The original bytes are
+    /// hashed because re-encoding a non-canonical dictionary would change them.
+    pub fn info_hash(&self) -> [u8; 20] {
+        Sha1::digest(&self.info_bytes).into()
+    }
+
+    /// SHA-256 of the info dictionary for v2 and hybrid torrents.
+    pub fn info_hash_v2(&self) -> Option<Sha256Hash> {
+        match self.info.version {
+            TorrentVersion::V1 => None,
+            _ => Some(Sha256::digest(&self.info_bytes).into()),
+        }
+    }
+
+    /// The v2 info-hash cut to 20 bytes, as trackers and the DHT expect it.
+    pub fn info_hash_v2_truncated(&self) -> Option<[u8; 20]> {
+        self.info_hash_v2().map(|hash| hash[..20].try_into().unwrap())
+    }
+
+    /// The 20 byte hash to announce: the v1 hash unless the torrent is v2 only.
+    pub fn tracker_info_hash(&self) -> [u8; 20] {
+        match self.info.version {
+            TorrentVersion::V2 => self.info_hash_v2_truncated().unwrap(),
+            _ => self.info_hash(),
+        }
+    }
+}
+
+fn field_name(prefix: &str, key: &str) -> String {
+    format!("{}{}", prefix, key)
+}
+
+fn invalid(field: &str, reason: impl Into<String>) -> MetainfoError {
+    MetainfoError::InvalidValue {
+        field: field.to_string(),
+        reason: reason.into(),
+    }
+}
+
+fn get_bytes<'a>(dict: &'a BDict, prefix: &str, key: &str) -> Result<Option<&'a [u8]>, MetainfoError> {
+    match dict.get(key.as_bytes()) {
+        None => Ok(None),
+        Some(value) => value.as_bytes().map(Some).ok_or_else(|| MetainfoError::WrongType {
+            field: field_name(prefix, key),
+            expected: "a string",
+        }),
+    }
+}
+
+// Plenty of torrents carry text in legacy encodings, so a string that is not
+// utf8 counts as missing rather than making the whole torrent unreadable.
+fn get_str(dict: &BDict, prefix: &str, key: &str) -> Result<Option<String>, MetainfoError> {
+    Ok(get_bytes(dict, prefix, key)?.and_then(|bytes| String::from_utf8(bytes.to_vec()).ok()))
+}
+
+// For text only shown to people, where a few replacement characters beat
+// losing the field.
+fn get_text(dict: &BDict, prefix: &str, key: &str) -> Result<Option<String>, MetainfoError> {
+    Ok(get_bytes(dict, prefix, key)?.map(|bytes| String::from_utf8_lossy(bytes).into_owned()))
+}
+
+// A url list entry, or None when it is not utf8 and so cannot be a usable url.
+fn url_entry(url: &Bencode) -> Option<Option<String>> {
+    let bytes = url.as_bytes()?;
+    Some(std::str::from_utf8(bytes).ok().map(str::to_string))
+}
+
+fn get_int(dict: &BDict, prefix: &str, key: &str) -> Result<Option<BInt>, MetainfoError> {
+    match dict.get(key.as_bytes()) {
+        None => Ok(None),
+        Some(value) => value.as_int().map(Some).ok_or_else(|| MetainfoError::WrongType {
+            field: field_name(prefix, key),
+            expected: "an integer",
+        }),
+    }
+}
+
+fn require<T>(value: Option<T>, prefix: &str, key: &str) -> Result<T, MetainfoError> {
+    value.ok_or_else(|| MetainfoError::MissingField {
+        field: field_name(prefix, key),
+    })
+}
+
+fn get_u64(dict: &BDict, prefix: &str, key: &str) -> Result<Option<u64>, MetainfoError> {
+    get_int(dict, prefix, key)?
+        .map(|int| u64::try_from(int).map_err(|_| invalid(&field_name(prefix, key), "must not be negative")))
+        .transpose()
+}
+
+fn parse_announce_list(dict: &BDict) -> Result<Vec<Vec<String>>, MetainfoError> {
+    let tiers = match dict.get(b"announce-list".as_slice()) {
+        None => return Ok(Vec::new()),
+        Some(tiers) => tiers.as_list().ok_or_else(|| MetainfoError::WrongType {
+            field: String::from("announce-list"),
+            expected: "a list of lists",
+        })?,
+    };
+    let mut announce_list = Vec::new();
+    for tier in tiers {
+        let tier = tier.as_list().ok_or_else(|| MetainfoError::WrongType {
+            field: String::from("announce-list"),
+            expected: "a list of lists",
+        })?;
+        let urls = tier
+            .iter()
+            .map(|url| {
+                url_entry(url).ok_or_else(|| MetainfoError::WrongType {
+                    field: String::from("announce-list"),
+                    expected: "a list of lists of urls",
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let urls: Vec<String> = urls.into_iter().flatten().collect();
+        // an empty tier carries no information
+        if !urls.is_empty() {
+            announce_list.push(urls);
+        }
+    }
+    Ok(announce_list)
+}
+
+// A single url may be given as a plain string instead of a list.
+fn parse_url_list(dict: &BDict, key: &str) -> Result<Vec<String>, MetainfoError> {
+    let wrong_type = || MetainfoError::WrongType {
+        field: key.to_string(),
+        expected: "a url or a list of urls",
+    };
+    match dict.get(key.as_bytes()) {
+        None => Ok(Vec::new()),
+        Some(Bencode::Str(_)) => Ok(get_str(dict, "", key)?.into_iter().collect()),
+        Some(Bencode::List(urls)) => {
+            let urls = urls.iter().map(|url| url_entry(url).ok_or_else(wrong_type)).collect::<Result<Vec<_>, _>>()?;
+            Ok(urls.into_iter().flatten().collect())
+        }
+        Some(_) => Err(wrong_type()),
+    }
+}
+
+fn parse_info(info: &BDict) -> Result<Info, MetainfoError> {
+    const PREFIX: &str = "info.";
+
+    let name = files::parse_name(info)?;
+
+    let piece_length = require(get_u64(info, PREFIX, "piece length")?, PREFIX, "piece length")?;
+    if piece_length == 0 {
+        return Err(invalid("info.piece length", "must be positive"));
+    }
+
+    let file_tree = match get_int(info, PREFIX, "meta version")? {
+        None => Vec::new(),
+        Some(2) => {
+            if !piece_length.is_power_of_two() || piece_length < BLOCK_SIZE {
+                return Err(invalid("info.piece length", "must be a power of two of at least 16 KiB"));
+            }
+            v2::parse_file_tree(require(info.get(b"file tree".as_slice()), PREFIX, "file tree")?)?
+        }
+        Some(version) => return Err(invalid("info.meta version", format!("unsupported version {}", version))),
+    };
+    let has_v1 = file_tree.is_empty() || info.contains_key(b"pieces".as_slice());
+    let version = match (has_v1, file_tree.is_empty()) {
+        (true, true) => TorrentVersion::V1,
+        (false, _) => TorrentVersion::V2,
+        (true, false) => TorrentVersion::Hybrid,
+    };
+
+    let (pieces, length, files) = if has_v1 {
+        parse_v1_pieces(info, piece_length)?
+    } else {
+        let length = file_tree
+            .iter()
+            .try_fold(0u64, |total, file| total.checked_add(file.length))
+            .ok_or_else(|| invalid("info.file tree", "total length overflows"))?;
+        (Vec::new(), length, v2::v1_files(&file_tree))
+    };
+
+    // a v2-only single file keeps its attributes in the file tree
+    let (attributes, symlink_path) = match (&files, file_tree.as_slice()) {
+        (None, [file]) if !has_v1 => (file.attributes, file.symlink_path.clone()),
+        _ => files::parse_attributes(info, PREFIX)?,
+    };
+
+    let private = match get_int(info, PREFIX, "private")? {
+        None | Some(0) => false,
+        Some(1) => true,
+        Some(_) => return Err(invalid("info.private", "must be 0 or 1")),
+    };
+
+    Ok(Info {
+        name,
+        piece_length,
+        pieces,
+        length,
+        files,
+        private,
+        version,
+        file_tree,
+        attributes,
+        symlink_path,
+    })
+}
+
+type V1Pieces = (Vec<PieceHash>, u64, Option<Vec<FileEntry>>);
+
+fn parse_v1_pieces(info: &BDict, piece_length: u64) -> Result<V1Pieces, MetainfoError> {
+    const PREFIX: &str = "info.";
+
+    let pieces = require(get_bytes(info, PREFIX, "pieces")?, PREFIX, "pieces")?;
+    if pieces.len() % PIECE_HASH_LEN != 0 {
+        return Err(invalid(
+            "info.pieces",
+            format!("length {} is not a multiple of {}", pieces.len(), PIECE_HASH_LEN),
+        ));
+    }
+    let pieces: Vec<PieceHash> = pieces
+        .chunks_exact(PIECE_HASH_LEN)
+        .map(|hash| hash.try_into().unwrap())
+        .collect();
+
+    let (length, files) = match (get_u64(info, PREFIX, "length")?, info.get(b"files".as_slice())) {
+        (Some(length), None) => (length, None),
+        (None, Some(files)) => {
+            let files = files::parse_files(files)?;
+            let length = files
+                .iter()
+                .try_fold(0u64, |total, file| total.checked_add(file.length))
+                .ok_or_else(|| invalid("info.files", "total length overflows"))?;
+            (length, Some(files))
+        }
+        _ => return Err(invalid("info", "must have exactly one of length and files")),
+    };
+    let expected_pieces = length.div_ceil(piece_length);
+    if pieces.len() as u64 != expected_pieces {
+        return Err(invalid(
+            "info.pieces",
+            format!("has {} hashes but {} bytes need {}", pieces.len(), length, expected_pieces),
+        ));
+    }
+    Ok((pieces, length, files))
+}
+
+#[cfg(test)]
+mod metainfo_tests {
+    use super::*;
+
+    #[test]
+    fn test_single_file() {
+        let metainfo = Metainfo::from_bytes(include_bytes!("../../tests/fixtures/single_file.torrent")).unwrap();
+        assert_eq!(metainfo.announce.as_deref(), Some("udp://tracker.opentrackr.org:1337/announce"));
+        assert!(metainfo.announce_list.is_empty());
+        assert_eq!(metainfo.comment.as_deref(), Some("single file fixture"));
+        assert_eq!(metainfo.created_by.as_deref(), Some("mktorrent 1.1"));
+        assert_eq!(metainfo.creation_date, Some(1700000000));
+        assert_eq!(metainfo.info.name, "single.bin");
+        assert_eq!(metainfo.info.length, 100000);
+        assert_eq!(metainfo.info.piece_length, 32768);
+        assert_eq!(metainfo.info.piece_count(), 4);
+        assert_eq!(metainfo.info.piece_size(0), Some(32768));
+        assert_eq!(metainfo.info.piece_size(3), Some(100000 - 3 * 32768));
+        assert_eq!(metainfo.info.piece_size(4), None);
+        assert!(!metainfo.info.private);
+    }
+
+    #[test]
+    fn test_fields() {
+        let metainfo = Metainfo::from_bytes(include_bytes!("../../tests/fixtures/private_webseed.torrent")).unwrap();
+        assert!(metainfo.info.private);
+        assert_eq!(
+            metainfo.web_seeds,
+            ["https://mirror1.example.org/pub/", "https://mirror2.example.org/pub/"]
+        );
+        assert!(metainfo.http_seeds.is_empty());
+        let metainfo = Metainfo::from_bytes(
+            b"d9:httpseedsl19:http://a.example/hse4:infod6:lengthi1e4:name1:a12:piece lengthi16384e\
+              6:pieces20:aaaaaaaaaaaaaaaaaaaae8:url-list17:http://b.example/e",
+        )
+        .unwrap();
+        assert_eq!(metainfo.http_seeds, ["http://a.example/hs"]);
+        assert_eq!(metainfo.web_seeds, ["http://b.example/"]);
+        let metainfo = Metainfo::from_bytes(include_bytes!("../../tests/fixtures/latin1_name.torrent")).unwrap();
+        assert_eq!(metainfo.info.name, "café.txt");
+        assert_eq!(metainfo.encoding.as_deref(), Some("ISO-8859-1"));
+    }
+
+    #[test]
+    fn test_legacy_encoded_text() {
+        let metainfo = Metainfo::from_bytes(
+            b"d8:announce12:http://t/\xe9/a13:announce-listll12:http://t/\xe9/a8:http://tee\
+              7:comment5:caf\xe9!10:created by3:\xff\xfeA4:infod6:lengthi1e4:name1:a\
+              10:name.utf-81:\xe912:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaae\
+              8:url-listl17:http://b.example/11:http://\xe9.x/ee",
+        )
+        .unwrap();
+        assert_eq!(metainfo.announce, None);
+        assert_eq!(metainfo.announce_list, [["http://t"]]);
+        assert_eq!(metainfo.comment.as_deref(), Some("caf\u{fffd}!"));
+        assert_eq!(metainfo.created_by.as_deref(), Some("\u{fffd}\u{fffd}A"));
+        assert_eq!(metainfo.web_seeds, ["http://b.example/"]);
+        // an unusable name.utf-8 falls back to name
+        assert_eq!(metainfo.info.name, "a");
+    }
+
+    #[test]
+    fn test_info_hash_uses_original_bytes() {
+        let bytes = include_bytes!("../../tests/fixtures/unsorted_info.torrent");
+        let metainfo = Metainfo::from_bytes(bytes).unwrap();
+        let start = bytes.windows(6).position(|window| window == b"4:info").unwrap() + 6;
+        assert_eq!(metainfo.info_bytes(), &bytes[start..bytes.len() - 1]);
+        assert_eq!(metainfo.info_hash(), <[u8; 20]>::from(Sha1::digest(metainfo.info_bytes())));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |input: &[u8]| Metainfo::from_bytes(input).unwrap_err().to_string();
+        assert_eq!(error(b"le"), "torrent is not a dictionary");
+        assert_eq!(error(b"de"), "missing field info");
+        assert_eq!(error(b"d4:infoi1ee"), "info is not a dictionary");
+        assert_eq!(error(b"d4:infod4:name1:aee"), "missing field info.piece length");
+        assert_eq!(
+            error(b"d4:infod6:lengthi1e4:name1:a12:piece lengthi-1eee"),
+            "invalid info.piece length: must not be negative"
+        );
+        assert_eq!(
+            error(b"d4:infod6:lengthi1e4:name1:a12:piece lengthi1e6:pieces3:abcee"),
+            "invalid info.pieces: length 3 is not a multiple of 20"
+        );
+        assert_eq!(
+            error(b"d4:infod6:lengthi2e4:name1:a12:piece lengthi1e6:pieces20:aaaaaaaaaaaaaaaaaaaaee"),
+            "invalid info.pieces: has 1 hashes but 2 bytes need 2"
+        );
+        assert_eq!(
+            error(b"d7:commenti1e4:infod6:lengthi1e4:name1:a12:piece lengthi1e6:pieces20:aaaaaaaaaaaaaaaaaaaaee"),
+            "comment should be a string"
+        );
+        assert_eq!(error(b"d4:info"), "invalid bencode: unexpected end of input at byte 7");
+        assert!(matches!(
+            Metainfo::from_file("does/not/exist.torrent"),
+            Err(MetainfoError::Io(_))
+        ));
+    }
+}
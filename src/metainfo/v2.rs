@@ -0,0 +1,359 @@
+use crate::bencode::{BDict, Bencode};
+use crate::metainfo::files::parse_attributes;
+use crate::metainfo::{
+    get_bytes, get_u64, invalid, require, validate_path_component, FileAttributes, FileEntry, MetainfoError,
+};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+
+/// Merkle trees of v2 torrents are built over blocks of this size.
+pub const BLOCK_SIZE: u64 = 16 * 1024;
+
+pub type Sha256Hash = [u8; 32];
+
+/// A file from the v2 `file tree`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct V2File {
+    /// Path components from the root of the file tree.
+    pub path: Vec<String>,
+    pub length: u64,
+    /// Merkle root over the file's blocks, absent for empty files.
+    pub pieces_root: Option<Sha256Hash>,
+    pub attributes: FileAttributes,
+    /// Target of a symlink as path components from the root of the file tree.
+    pub symlink_path: Option<Vec<String>>,
+}
+
+/// Merkle hashes computed from a file's contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileHashes {
+    pub pieces_root: Option<Sha256Hash>,
+    /// One hash per piece. Only stored in `piece layers` for files longer
+    /// than one piece.
+    pub piece_layer: Vec<Sha256Hash>,
+}
+
+fn hash_pair(left: &Sha256Hash, right: &Sha256Hash) -> Sha256Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Root of a tree over `leaves` padded to `width` leaves with `pad`, where
+/// `width` is a power of two. Padding is hashed lazily, one pair per level.
+pub fn merkle_root(leaves: &[Sha256Hash], width: usize, pad: Sha256Hash) -> Sha256Hash {
+    debug_assert!(width.is_power_of_two() && leaves.len() <= width);
+    let mut layer = leaves.to_vec();
+    let mut pad = pad;
+    let mut width = width;
+    while width > 1 {
+        if layer.len() % 2 == 1 {
+            layer.push(pad);
+        }
+        layer = layer.chunks_exact(2).map(|pair| hash_pair(&pair[0], &pair[1])).collect();
+        pad = hash_pair(&pad, &pad);
+        width /= 2;
+    }
+    layer.first().copied().unwrap_or(pad)
+}
+
+// Root of a piece whose blocks are all past the end of the file.
+fn zero_piece_root(piece_length: u64) -> Sha256Hash {
+    merkle_root(&[], (piece_length / BLOCK_SIZE) as usize, [0; 32])
+}
+
+/// Recomputes a file's `pieces root` from its piece layer.
+pub fn root_from_piece_layer(layer: &[Sha256Hash], piece_length: u64) -> Sha256Hash {
+    merkle_root(layer, layer.len().next_power_of_two(), zero_piece_root(piece_length))
+}
+
+/// Hashes a file's contents into its v2 merkle root and piece layer.
+/// `piece_length` must be a power of two of at least [`BLOCK_SIZE`].
+pub fn hash_file(mut reader: impl Read, piece_length: u64) -> io::Result<FileHashes> {
+    let mut blocks = Vec::new();
+    let mut length = 0;
+    let mut buffer = vec![0; BLOCK_SIZE as usize];
+    loop {
+        let read = read_block(&mut reader, &mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        blocks.push(Sha256::digest(&buffer[..read]).into());
+        length += read as u64;
+    }
+    if length == 0 {
+        return Ok(FileHashes {
+            pieces_root: None,
+            piece_layer: Vec::new(),
+        });
+    }
+
+    let blocks_per_piece = (piece_length / BLOCK_SIZE) as usize;
+    let piece_layer: Vec<Sha256Hash> = blocks
+        .chunks(blocks_per_piece)
+        .map(|piece| merkle_root(piece, blocks_per_piece, [0; 32]))
+        .collect();
+    let pieces_root = if length <= piece_length {
+        merkle_root(&blocks, blocks.len().next_power_of_two(), [0; 32])
+    } else {
+        root_from_piece_layer(&piece_layer, piece_length)
+    };
+    Ok(FileHashes {
+        pieces_root: Some(pieces_root),
+        piece_layer,
+    })
+}
+
+// Fills `buffer` unless the reader runs out first.
+fn read_block(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+pub(crate) fn parse_file_tree(tree: &Bencode) -> Result<Vec<V2File>, MetainfoError> {
+    let tree = tree.as_dict().ok_or_else(|| MetainfoError::WrongType {
+        field: String::from("info.file tree"),
+        expected: "a dictionary",
+    })?;
+    let mut files = Vec::new();
+    walk_tree(tree, &mut Vec::new(), &mut files)?;
+    if files.is_empty() {
+        return Err(invalid("info.file tree", "must contain at least one file"));
+    }
+    Ok(files)
+}
+
+// A key of "" marks a file; every other key is a path component.
+fn walk_tree(node: &BDict, path: &mut Vec<String>, files: &mut Vec<V2File>) -> Result<(), MetainfoError> {
+    let field = format!("info.file tree/{}", path.join("/"));
+    if let Some(file) = node.get(b"".as_slice()) {
+        if path.is_empty() || node.len() != 1 {
+            return Err(invalid(&field, "file entry must be the only key of its node"));
+        }
+        let file = file.as_dict().ok_or_else(|| MetainfoError::WrongType {
+            field: field.clone(),
+            expected: "a dictionary",
+        })?;
+        let prefix = format!("{}.", field);
+        let length = require(get_u64(file, &prefix, "length")?, &prefix, "length")?;
+        let pieces_root = match get_bytes(file, &prefix, "pieces root")? {
+            Some(root) => Some(
+                Sha256Hash::try_from(root)
+                    .map_err(|_| invalid(&format!("{}pieces root", prefix), "must be 32 bytes"))?,
+            ),
+            None if length == 0 => None,
+            None => return Err(MetainfoError::MissingField { field: format!("{}pieces root", prefix) }),
+        };
+        let (attributes, symlink_path) = parse_attributes(file, &prefix)?;
+        files.push(V2File {
+            path: path.clone(),
+            length,
+            pieces_root,
+            attributes,
+            symlink_path,
+        });
+        return Ok(());
+    }
+    for (key, child) in node {
+        let component = String::from_utf8(key.clone()).map_err(|_| invalid(&field, "path is not valid utf8"))?;
+        validate_path_component(&component).map_err(|reason| invalid(&field, reason))?;
+        let child = child.as_dict().ok_or_else(|| MetainfoError::WrongType {
+            field: field.clone(),
+            expected: "a dictionary",
+        })?;
+        path.push(component);
+        walk_tree(child, path, files)?;
+        path.pop();
+    }
+    Ok(())
+}
+
+/// A v2 file tree seen as a v1 `files` list. A tree holding a single file
+/// at the top level is a single-file torrent.
+pub(crate) fn v1_files(file_tree: &[V2File]) -> Option<Vec<FileEntry>> {
+    match file_tree {
+        [file] if file.path.len() == 1 => None,
+        _ => Some(
+            file_tree
+                .iter()
+                .map(|file| FileEntry {
+                    path: file.path.clone(),
+                    length: file.length,
+                    attributes: file.attributes,
+                    symlink_path: file.symlink_path.clone(),
+                })
+                .collect(),
+        ),
+    }
+}
+
+/// Parses `piece layers` and checks every layer against the `pieces root`
+/// it is keyed by. Files longer than a piece must have a layer.
+pub(crate) fn parse_piece_layers(
+    layers: Option<&Bencode>,
+    file_tree: &[V2File],
+    piece_length: u64,
+) -> Result<BTreeMap<Sha256Hash, Vec<Sha256Hash>>, MetainfoError> {
+    let mut piece_layers = BTreeMap::new();
+    if let Some(layers) = layers {
+        let layers = layers.as_dict().ok_or_else(|| MetainfoError::WrongType {
+            field: String::from("piece layers"),
+            expected: "a dictionary",
+        })?;
+        for (root, layer) in layers {
+            let root = Sha256Hash::try_from(root.as_slice())
+                .map_err(|_| invalid("piece layers", "keys must be 32 byte roots"))?;
+            let layer = layer.as_bytes().ok_or_else(|| MetainfoError::WrongType {
+                field: String::from("piece layers"),
+                expected: "a dictionary of strings",
+            })?;
+            if layer.len() % 32 != 0 {
+                return Err(invalid("piece layers", "layer length is not a multiple of 32"));
+            }
+            let layer: Vec<Sha256Hash> = layer.chunks_exact(32).map(|hash| hash.try_into().unwrap()).collect();
+            piece_layers.insert(root, layer);
+        }
+    }
+
+    for file in file_tree.iter().filter(|file| file.length > piece_length) {
+        let field = format!("piece layers for {}", file.path.join("/"));
+        let root = file.pieces_root.expect("non-empty files have a pieces root");
+        let layer = piece_layers
+            .get(&root)
+            .ok_or_else(|| MetainfoError::MissingField { field: field.clone() })?;
+        let expected = file.length.div_ceil(piece_length);
+        if layer.len() as u64 != expected {
+            return Err(invalid(
+                &field,
+                format!("has {} hashes but {} bytes need {}", layer.len(), file.length, expected),
+            ));
+        }
+        if root_from_piece_layer(layer, piece_length) != root {
+            return Err(invalid(&field, "does not match the pieces root"));
+        }
+    }
+    Ok(piece_layers)
+}
+
+#[cfg(test)]
+mod v2_tests {
+    use super::*;
+    use crate::metainfo::{Metainfo, TorrentVersion};
+    use data_encoding::HEXLOWER;
+
+    // Contents the fixtures were generated from.
+    fn big() -> Vec<u8> {
+        (0..100000u32).map(|i| ((i * 31 + 7) % 256) as u8).collect()
+    }
+
+    fn small() -> Vec<u8> {
+        (0..1000u32).map(|i| ((i * 13) % 256) as u8).collect()
+    }
+
+    #[test]
+    fn test_v2_only() {
+        let metainfo = Metainfo::from_bytes(include_bytes!("../../tests/fixtures/v2_only.torrent")).unwrap();
+        let info = &metainfo.info;
+        assert_eq!(info.version, TorrentVersion::V2);
+        assert!(info.pieces.is_empty());
+        assert_eq!(info.length, 101000);
+        assert_eq!(info.file_tree.len(), 3);
+        assert_eq!(info.file_tree[0].path, ["dir", "big.bin"]);
+        assert_eq!(info.file_tree[2].pieces_root, None);
+        assert_eq!(info.files.as_ref().unwrap()[1].path, ["small.txt"]);
+        assert_eq!(metainfo.piece_layers.len(), 1);
+        assert_eq!(
+            HEXLOWER.encode(&metainfo.info_hash_v2().unwrap()),
+            "d76bd62f20fb9db2c41fe6ec9a310bc36c7a10beb1c4b972b9020f1e680c8cfa"
+        );
+        assert_eq!(
+            HEXLOWER.encode(&metainfo.info_hash_v2_truncated().unwrap()),
+            "d76bd62f20fb9db2c41fe6ec9a310bc36c7a10be"
+        );
+        assert_eq!(metainfo.tracker_info_hash(), metainfo.info_hash_v2_truncated().unwrap());
+    }
+
+    #[test]
+    fn test_hybrid() {
+        let metainfo = Metainfo::from_bytes(include_bytes!("../../tests/fixtures/hybrid.torrent")).unwrap();
+        assert_eq!(metainfo.info.version, TorrentVersion::Hybrid);
+        assert_eq!(metainfo.info.piece_count(), 5);
+        assert_eq!(metainfo.info.files.as_ref().unwrap()[1].path, [".pad", "31072"]);
+        assert!(metainfo.info.files.as_ref().unwrap()[1].attributes.padding);
+        assert!(!metainfo.info.files.as_ref().unwrap()[0].attributes.padding);
+        assert_eq!(
+            HEXLOWER.encode(&metainfo.info_hash_v2().unwrap()),
+            "60c54bad0071d3039a57236acf372f0d713586560a35a07eff69e4f4a5a48961"
+        );
+        assert_eq!(HEXLOWER.encode(&metainfo.info_hash()), "e5637b714bdd958e9fb56944969a48e8f8019d70");
+        assert_eq!(metainfo.tracker_info_hash(), metainfo.info_hash());
+
+        let v1 = Metainfo::from_bytes(include_bytes!("../../tests/fixtures/single_file.torrent")).unwrap();
+        assert_eq!(v1.info.version, TorrentVersion::V1);
+        assert_eq!(v1.info_hash_v2(), None);
+    }
+
+    #[test]
+    fn test_verify_files() {
+        let metainfo = Metainfo::from_bytes(include_bytes!("../../tests/fixtures/v2_only.torrent")).unwrap();
+        let info = &metainfo.info;
+        let hashes = hash_file(big().as_slice(), info.piece_length).unwrap();
+        assert_eq!(hashes.piece_layer, metainfo.piece_layers[&hashes.pieces_root.unwrap()]);
+        assert!(info.verify_file(&info.file_tree[0], big().as_slice()).unwrap());
+        assert!(info.verify_file(&info.file_tree[1], small().as_slice()).unwrap());
+        assert!(info.verify_file(&info.file_tree[2], io::empty()).unwrap());
+        let mut damaged = small();
+        damaged[500] ^= 1;
+        assert!(!info.verify_file(&info.file_tree[1], damaged.as_slice()).unwrap());
+        assert!(!info.verify_file(&info.file_tree[0], small().as_slice()).unwrap());
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        let leaf = |byte| [byte; 32];
+        assert_eq!(merkle_root(&[leaf(1)], 1, leaf(0)), leaf(1));
+        assert_eq!(
+            merkle_root(&[leaf(1)], 4, leaf(0)),
+            hash_pair(&hash_pair(&leaf(1), &leaf(0)), &hash_pair(&leaf(0), &leaf(0)))
+        );
+        assert_eq!(merkle_root(&[], 2, leaf(0)), hash_pair(&leaf(0), &leaf(0)));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut bytes = include_bytes!("../../tests/fixtures/v2_only.torrent").to_vec();
+        let last = bytes.len() - 3;
+        bytes[last] ^= 1;
+        assert_eq!(
+            Metainfo::from_bytes(&bytes).unwrap_err().to_string(),
+            "invalid piece layers for dir/big.bin: does not match the pieces root"
+        );
+        let error = |input: &[u8]| Metainfo::from_bytes(input).unwrap_err().to_string();
+        assert_eq!(
+            error(b"d4:infod9:file treed1:ad0:d6:lengthi1eeee12:meta versioni2e4:name1:a12:piece lengthi16384eee"),
+            "missing field info.file tree/a.pieces root"
+        );
+        assert_eq!(
+            error(b"d4:infod9:file treed2:..d0:d6:lengthi0eeee12:meta versioni2e4:name1:a12:piece lengthi16384eee"),
+            "invalid info.file tree/: path component \"..\" leaves the torrent directory"
+        );
+        assert_eq!(
+            error(b"d4:infod9:file treed1:ad0:d6:lengthi0eeee12:meta versioni2e4:name1:a12:piece lengthi1000eee"),
+            "invalid info.piece length: must be a power of two of at least 16 KiB"
+        );
+        assert_eq!(
+            error(b"d4:infod9:file treed1:ad0:d6:lengthi0eeee12:meta versioni3e4:name1:a12:piece lengthi16384eee"),
+            "invalid info.meta version: unsupported version 3"
+        );
+    }
+}
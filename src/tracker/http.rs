@@ -0,0 +1,412 @@
+use crate::bencode::{decode_bencode, BDict, Bencode};
+use crate::tracker::types::{AnnounceRequest, AnnounceResponse, ConnectionRequestAction, ScrapeStats};
+use crate::tracker::TrackerError;
+use percent_encoding::{percent_encode, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::blocking::Client;
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+// Everything but RFC 3986 unreserved characters, so binary hashes and IDs
+// survive the trip byte for byte.
+const QUERY_VALUE: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+/// Talks to HTTP and HTTPS trackers (BEP 3, with BEP 23 compact peers and
+/// BEP 7 IPv6 peers).
+#[derive(Debug, Clone, Default)]
+pub struct HttpTrackerClient {
+    client: Client,
+}
+
+impl HttpTrackerClient {
+    pub fn new() -> Self {
+        HttpTrackerClient::default()
+    }
+
+    /// Announces `request` to the tracker at `url`. `tracker_id` is the
+    /// [`AnnounceResponse::tracker_id`] of the tracker's previous answer.
+    pub fn announce(
+        &self,
+        url: &str,
+        request: &AnnounceRequest,
+        tracker_id: Option<&str>,
+    ) -> Result<AnnounceResponse, TrackerError> {
+        let url = announce_url(url, request, tracker_id)?;
+        let response = self.client.get(url).send()?;
+        let status = response.status();
+        let body = response.bytes()?;
+        match parse_announce(&body) {
+            // trackers often explain a refusal in the body of an error status
+            Err(TrackerError::InvalidResponse) if !status.is_success() => Err(TrackerError::Status(status.as_u16())),
+            result => result,
+        }
+    }
+
+    /// Asks the tracker with announce url `url` for the swarm statistics of
+    /// `info_hashes`, all in one request. Hashes the tracker does not know
+    /// are missing from the result.
+    pub fn scrape(&self, url: &str, info_hashes: &[[u8; 20]]) -> Result<BTreeMap<[u8; 20], ScrapeStats>, TrackerError> {
+        let mut url = scrape_url(url).ok_or(TrackerError::ScrapeUnsupported)?;
+        let mut separator = if url.contains('?') { '&' } else { '?' };
+        for info_hash in info_hashes {
+            url.push(separator);
+            url.push_str("info_hash=");
+            url.push_str(&percent_encode(info_hash, QUERY_VALUE).to_string());
+            separator = '&';
+        }
+        let response = self.client.get(url).send()?;
+        let status = response.status();
+        let body = response.bytes()?;
+        match parse_scrape(&body) {
+            Err(TrackerError::InvalidResponse) if !status.is_success() => Err(TrackerError::Status(status.as_u16())),
+            result => result,
+        }
+    }
+}
+
+/// The scrape url of a tracker by the BEP 48 convention: the last path
+/// segment of the announce url must start with `announce`, which is replaced
+/// by `scrape`. Returns `None` for trackers that cannot be scraped this way.
+pub fn scrape_url(announce_url: &str) -> Option<String> {
+    let path_end = announce_url.find('?').unwrap_or(announce_url.len());
+    let segment = announce_url[..path_end].rfind('/')? + 1;
+    let rest = announce_url[segment..].strip_prefix("announce")?;
+    Some(format!("{}scrape{}", &announce_url[..segment], rest))
+}
+
+fn announce_url(url: &str, request: &AnnounceRequest, tracker_id: Option<&str>) -> Result<String, TrackerError> {
+    let scheme = url.split_once("://").map(|(scheme, _)| scheme.to_ascii_lowercase());
+    match scheme.as_deref() {
+        Some("http") | Some("https") => {}
+        Some(scheme) => return Err(TrackerError::UnsupportedScheme(scheme.to_string())),
+        None => return Err(TrackerError::InvalidUrl(url.to_string())),
+    }
+    let mut params = vec![
+        ("info_hash", percent_encode(&request.info_hash, QUERY_VALUE).to_string()),
+        ("peer_id", percent_encode(&request.peer_id, QUERY_VALUE).to_string()),
+        ("port", request.port.to_string()),
+        ("uploaded", request.uploaded.to_string()),
+        ("downloaded", request.downloaded.to_string()),
+        ("left", request.left.to_string()),
+        ("compact", String::from("1")),
+        ("key", format!("{:08x}", request.key)),
+    ];
+    // events use the UDP codes; 0 means a regular announce and is left out
+    let event = match request.event {
+        1 => Some("completed"),
+        2 => Some("started"),
+        3 => Some("stopped"),
+        _ => None,
+    };
+    if let Some(event) = event {
+        params.push(("event", event.to_string()));
+    }
+    if request.ip_address != 0 {
+        params.push(("ip", Ipv4Addr::from(request.ip_address as u32).to_string()));
+    }
+    if request.num_want >= 0 {
+        params.push(("numwant", request.num_want.to_string()));
+    }
+    if let Some(tracker_id) = tracker_id {
+        params.push(("trackerid", utf8_percent_encode(tracker_id, QUERY_VALUE).to_string()));
+    }
+
+    let mut url = url.to_string();
+    let mut separator = if url.contains('?') { '&' } else { '?' };
+    for (name, value) in params {
+        url.push(separator);
+        url.push_str(name);
+        url.push('=');
+        url.push_str(&value);
+        separator = '&';
+    }
+    Ok(url)
+}
+
+fn parse_announce(body: &[u8]) -> Result<AnnounceResponse, TrackerError> {
+    let response = decode_bencode(body).map_err(|_| TrackerError::InvalidResponse)?;
+    let response = response.as_dict().ok_or(TrackerError::InvalidResponse)?;
+    if let Some(reason) = get_str(response, "failure reason")? {
+        return Err(TrackerError::Tracker(reason));
+    }
+    let mut peers = match response.get(b"peers".as_slice()) {
+        None => Vec::new(),
+        Some(Bencode::Str(compact)) => compact
+            .chunks_exact(6)
+            .map(|peer| {
+                let ip = Ipv4Addr::new(peer[0], peer[1], peer[2], peer[3]);
+                (ip.to_string(), u16::from_be_bytes([peer[4], peer[5]]))
+            })
+            .collect(),
+        Some(Bencode::List(list)) => list.iter().map(parse_peer).collect::<Result<_, _>>()?,
+        Some(_) => return Err(TrackerError::InvalidResponse),
+    };
+    match response.get(b"peers6".as_slice()) {
+        None => {}
+        Some(Bencode::Str(compact)) => peers.extend(compact.chunks_exact(18).map(|peer| {
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&peer[..16]).unwrap());
+            (ip.to_string(), u16::from_be_bytes([peer[16], peer[17]]))
+        })),
+        Some(_) => return Err(TrackerError::InvalidResponse),
+    }
+
+    Ok(AnnounceResponse {
+        action: ConnectionRequestAction::ANNOUNCE,
+        transaction_id: 0,
+        interval: get_i32(response, "interval")?.ok_or(TrackerError::InvalidResponse)?,
+        leechers: get_i32(response, "incomplete")?.unwrap_or(0),
+        seeders: get_i32(response, "complete")?.unwrap_or(0),
+        peers,
+        min_interval: get_i32(response, "min interval")?,
+        tracker_id: get_str(response, "tracker id")?,
+        warning: get_str(response, "warning message")?,
+    })
+}
+
+fn parse_scrape(body: &[u8]) -> Result<BTreeMap<[u8; 20], ScrapeStats>, TrackerError> {
+    let response = decode_bencode(body).map_err(|_| TrackerError::InvalidResponse)?;
+    let response = response.as_dict().ok_or(TrackerError::InvalidResponse)?;
+    if let Some(reason) = get_str(response, "failure reason")? {
+        return Err(TrackerError::Tracker(reason));
+    }
+    let files = match response.get(b"files".as_slice()) {
+        None => return Ok(BTreeMap::new()),
+        Some(files) => files.as_dict().ok_or(TrackerError::InvalidResponse)?,
+    };
+    files
+        .iter()
+        .map(|(info_hash, stats)| {
+            let info_hash = <[u8; 20]>::try_from(info_hash.as_slice()).map_err(|_| TrackerError::InvalidResponse)?;
+            let stats = stats.as_dict().ok_or(TrackerError::InvalidResponse)?;
+            let stats = ScrapeStats {
+                seeders: get_i32(stats, "complete")?.unwrap_or(0),
+                completed: get_i32(stats, "downloaded")?.unwrap_or(0),
+                leechers: get_i32(stats, "incomplete")?.unwrap_or(0),
+                name: get_str(stats, "name")?,
+            };
+            Ok((info_hash, stats))
+        })
+        .collect()
+}
+
+// An entry of the original, non-compact peer list. `ip` may also be a
+// host name.
+fn parse_peer(peer: &Bencode) -> Result<(String, u16), TrackerError> {
+    let peer = peer.as_dict().ok_or(TrackerError::InvalidResponse)?;
+    let ip = get_str(peer, "ip")?.ok_or(TrackerError::InvalidResponse)?;
+    let port = peer
+        .get(b"port".as_slice())
+        .and_then(Bencode::as_int)
+        .and_then(|port| u16::try_from(port).ok())
+        .ok_or(TrackerError::InvalidResponse)?;
+    Ok((ip, port))
+}
+
+fn get_i32(dict: &BDict, key: &str) -> Result<Option<i32>, TrackerError> {
+    match dict.get(key.as_bytes()) {
+        None => Ok(None),
+        Some(value) => value
+            .as_int()
+            .and_then(|int| i32::try_from(int).ok())
+            .map(Some)
+            .ok_or(TrackerError::InvalidResponse),
+    }
+}
+
+fn get_str(dict: &BDict, key: &str) -> Result<Option<String>, TrackerError> {
+    match dict.get(key.as_bytes()) {
+        None => Ok(None),
+        Some(value) => value
+            .as_bytes()
+            .map(|bytes| Some(String::from_utf8_lossy(bytes).into_owned()))
+            .ok_or(TrackerError::InvalidResponse),
+    }
+}
+
+#[cfg(test)]
+mod http_tests {
+    use super::*;
+    use crate::bencode::encode_bencode;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use tiny_http::{Response, Server};
+
+    // Serves `body` with `status` to every request and records request urls.
+    fn stand_in(status: u16, body: Vec<u8>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/announce", server.server_addr().to_ip().unwrap());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                log.lock().unwrap().push(request.url().to_string());
+                request
+                    .respond(Response::from_data(body.clone()).with_status_code(status))
+                    .unwrap();
+            }
+        });
+        (url, seen)
+    }
+
+    fn dict(entries: Vec<(&str, Bencode)>) -> Bencode {
+        Bencode::Dict(entries.into_iter().map(|(key, value)| (key.as_bytes().to_vec(), value)).collect())
+    }
+
+    fn request() -> AnnounceRequest {
+        let mut request = AnnounceRequest::new(&0, [0x00, 0x41, 0xff, 0x7e, 0x20].repeat(4));
+        request.peer_id = b"-PC0001-\x01\x02abcdefghij".to_vec();
+        request.port = 51413;
+        request.left = 1000;
+        request.event = 2;
+        request.key = 0xbeef;
+        request.num_want = 50;
+        request
+    }
+
+    #[test]
+    fn test_request_parameters() {
+        let body = encode_bencode(&dict(vec![("interval", Bencode::Int(1800))]));
+        let (url, seen) = stand_in(200, body);
+        let client = HttpTrackerClient::new();
+        client.announce(&format!("{}?passkey=x", url), &request(), Some("t 1")).unwrap();
+        assert_eq!(
+            seen.lock().unwrap()[0],
+            format!(
+                "/announce?passkey=x&info_hash={}&peer_id=-PC0001-%01%02abcdefghij&port=51413&uploaded=0\
+                 &downloaded=0&left=1000&compact=1&key=0000beef&event=started&numwant=50&trackerid=t%201",
+                "%00A%FF~%20".repeat(4)
+            )
+        );
+    }
+
+    #[test]
+    fn test_compact_response() {
+        let mut peers6 = vec![0; 15];
+        peers6.extend([1, 0x1a, 0xe1]);
+        let body = encode_bencode(&dict(vec![
+            ("complete", Bencode::Int(5)),
+            ("incomplete", Bencode::Int(2)),
+            ("interval", Bencode::Int(1800)),
+            ("min interval", Bencode::Int(900)),
+            ("peers", Bencode::Str(vec![10, 0, 0, 1, 0xc8, 0xd5, 192, 168, 1, 2, 0x1a, 0xe1])),
+            ("peers6", Bencode::Str(peers6)),
+            ("tracker id", Bencode::new_str("abc")),
+            ("warning message", Bencode::new_str("slow down")),
+        ]));
+        let (url, _) = stand_in(200, body);
+        let response = HttpTrackerClient::new().announce(&url, &request(), None).unwrap();
+        assert_eq!(response.interval, 1800);
+        assert_eq!(response.min_interval, Some(900));
+        assert_eq!((response.seeders, response.leechers), (5, 2));
+        assert_eq!(response.tracker_id.as_deref(), Some("abc"));
+        assert_eq!(response.warning.as_deref(), Some("slow down"));
+        assert_eq!(
+            response.peers,
+            [
+                (String::from("10.0.0.1"), 51413),
+                (String::from("192.168.1.2"), 6881),
+                (String::from("::1"), 6881)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dictionary_peers() {
+        let peer = |ip: &str, port| {
+            dict(vec![
+                ("ip", Bencode::new_str(ip)),
+                ("peer id", Bencode::Str(vec![0xab; 20])),
+                ("port", Bencode::Int(port)),
+            ])
+        };
+        let body = encode_bencode(&dict(vec![
+            ("interval", Bencode::Int(60)),
+            ("peers", Bencode::List(vec![peer("127.0.0.1", 6881), peer("peer.example.org", 40000)])),
+        ]));
+        let (url, _) = stand_in(200, body);
+        let response = HttpTrackerClient::new().announce(&url, &request(), None).unwrap();
+        assert_eq!(
+            response.peers,
+            [(String::from("127.0.0.1"), 6881), (String::from("peer.example.org"), 40000)]
+        );
+        assert_eq!(response.min_interval, None);
+    }
+
+    #[test]
+    fn test_scrape_url() {
+        let scrape = |url| scrape_url(url);
+        assert_eq!(scrape("http://example.com/announce").as_deref(), Some("http://example.com/scrape"));
+        assert_eq!(scrape("http://example.com/x/announce").as_deref(), Some("http://example.com/x/scrape"));
+        assert_eq!(scrape("http://example.com/announce.php").as_deref(), Some("http://example.com/scrape.php"));
+        assert_eq!(scrape("http://example.com/a"), None);
+        assert_eq!(scrape("http://example.com/announce?x2%0644").as_deref(), Some("http://example.com/scrape?x2%0644"));
+        assert_eq!(scrape("http://example.com/announce?x=2/4").as_deref(), Some("http://example.com/scrape?x=2/4"));
+        assert_eq!(scrape("http://example.com/x%064announce"), None);
+    }
+
+    #[test]
+    fn test_scrape() {
+        let stats = |complete, downloaded, incomplete, name: Option<&str>| {
+            let mut entries = vec![
+                ("complete", Bencode::Int(complete)),
+                ("downloaded", Bencode::Int(downloaded)),
+                ("incomplete", Bencode::Int(incomplete)),
+            ];
+            entries.extend(name.map(|name| ("name", Bencode::new_str(name))));
+            dict(entries)
+        };
+        let files = Bencode::Dict(
+            [(vec![1; 20], stats(5, 50, 2, Some("release.iso"))), (vec![0xff; 20], stats(0, 3, 1, None))]
+                .into_iter()
+                .collect(),
+        );
+        let (url, seen) = stand_in(200, encode_bencode(&dict(vec![("files", files)])));
+        let client = HttpTrackerClient::new();
+        let result = client.scrape(&format!("{}.php?key=1", url), &[[1; 20], [0xff; 20], [2; 20]]).unwrap();
+        assert_eq!(
+            seen.lock().unwrap()[0],
+            format!(
+                "/scrape.php?key=1&info_hash={}&info_hash={}&info_hash={}",
+                "%01".repeat(20),
+                "%FF".repeat(20),
+                "%02".repeat(20)
+            )
+        );
+        assert_eq!(result.len(), 2);
+        assert_eq!(
+            result[&[1; 20]],
+            ScrapeStats {
+                seeders: 5,
+                completed: 50,
+                leechers: 2,
+                name: Some(String::from("release.iso"))
+            }
+        );
+        assert_eq!((result[&[0xff; 20]].completed, result[&[0xff; 20]].name.clone()), (3, None));
+
+        assert!(matches!(
+            client.scrape("http://example.com/a", &[[1; 20]]),
+            Err(TrackerError::ScrapeUnsupported)
+        ));
+        let (url, _) = stand_in(200, b"d5:filesd3:abcdeee".to_vec());
+        assert!(matches!(client.scrape(&url, &[[1; 20]]), Err(TrackerError::InvalidResponse)));
+    }
+
+    #[test]
+    fn test_errors() {
+        let client = HttpTrackerClient::new();
+        let body = encode_bencode(&dict(vec![("failure reason", Bencode::new_str("unregistered torrent"))]));
+        let (url, _) = stand_in(200, body);
+        match client.announce(&url, &request(), None) {
+            Err(TrackerError::Tracker(reason)) => assert_eq!(reason, "unregistered torrent"),
+            other => panic!("unexpected {:?}", other),
+        }
+        let (url, _) = stand_in(200, b"d5:peersi1ee".to_vec());
+        assert!(matches!(client.announce(&url, &request(), None), Err(TrackerError::InvalidResponse)));
+        let (url, _) = stand_in(404, b"not found".to_vec());
+        assert!(matches!(client.announce(&url, &request(), None), Err(TrackerError::Status(404))));
+        assert!(matches!(
+            client.announce("udp://t.example:1/announce", &request(), None),
+            Err(TrackerError::UnsupportedScheme(_))
+        ));
+    }
+}
@@ -0,0 +1,172 @@
+use crate::metainfo::Metainfo;
+use rand::seq::SliceRandom;
+use rand::{thread_rng, Rng};
+
+/// Trackers of a torrent grouped into BEP 12 tiers.
+///
+/// Each tier is shuffled once on creation. Trackers are then tried tier by
+/// tier, in order, and a tracker that answers moves to the front of its tier
+/// so it is tried first next time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackerTiers {
+    tiers: Vec<Vec<String>>,
+}
+
+impl TrackerTiers {
+    pub fn new(tiers: Vec<Vec<String>>) -> Self {
+        TrackerTiers::with_rng(tiers, &mut thread_rng())
+    }
+
+    /// Like [`TrackerTiers::new`] with a caller supplied shuffle.
+    pub fn with_rng(tiers: Vec<Vec<String>>, rng: &mut impl Rng) -> Self {
+        let mut seen = Vec::new();
+        let mut tiers: Vec<Vec<String>> = tiers
+            .into_iter()
+            .map(|tier| {
+                // a url listed twice is only worth asking once
+                let tier: Vec<String> = tier.into_iter().filter(|url| !seen.contains(url)).collect();
+                seen.extend(tier.iter().cloned());
+                tier
+            })
+            .filter(|tier| !tier.is_empty())
+            .collect();
+        for tier in &mut tiers {
+            tier.shuffle(rng);
+        }
+        TrackerTiers { tiers }
+    }
+
+    /// Uses `announce-list` when present and ignores `announce`, as BEP 12
+    /// asks; otherwise `announce` is the only tier.
+    pub fn from_metainfo(metainfo: &Metainfo) -> Self {
+        if metainfo.announce_list.is_empty() {
+            TrackerTiers::new(metainfo.announce.iter().map(|url| vec![url.clone()]).collect())
+        } else {
+            TrackerTiers::new(metainfo.announce_list.clone())
+        }
+    }
+
+    pub fn tiers(&self) -> &[Vec<String>] {
+        &self.tiers
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiers.is_empty()
+    }
+
+    /// Every tracker in the order they would be tried.
+    pub fn urls(&self) -> impl Iterator<Item = &str> {
+        self.tiers.iter().flatten().map(String::as_str)
+    }
+
+    /// Moves `url` to the front of its tier. Unknown urls are ignored.
+    pub fn promote(&mut self, url: &str) {
+        for tier in &mut self.tiers {
+            if let Some(index) = tier.iter().position(|tracker| tracker == url) {
+                tier[..=index].rotate_right(1);
+                return;
+            }
+        }
+    }
+
+    /// Calls `request` for each tracker in order until one succeeds, promotes
+    /// that tracker and returns its url and result. Returns the last error
+    /// when every tracker fails, or `None` if there are no trackers.
+    pub fn announce<T, E>(
+        &mut self,
+        mut request: impl FnMut(&str) -> Result<T, E>,
+    ) -> Option<Result<(String, T), E>> {
+        let mut last_error = None;
+        for tier in &mut self.tiers {
+            for index in 0..tier.len() {
+                match request(&tier[index]) {
+                    Ok(response) => {
+                        tier[..=index].rotate_right(1);
+                        return Some(Ok((tier[0].clone(), response)));
+                    }
+                    Err(e) => last_error = Some(e),
+                }
+            }
+        }
+        last_error.map(Err)
+    }
+}
+
+#[cfg(test)]
+mod tiers_tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn urls(urls: &[&str]) -> Vec<String> {
+        urls.iter().map(|url| url.to_string()).collect()
+    }
+
+    fn tiers() -> TrackerTiers {
+        TrackerTiers {
+            tiers: vec![urls(&["a", "b", "c"]), urls(&["d", "e"])],
+        }
+    }
+
+    #[test]
+    fn test_shuffle_keeps_tiers() {
+        let input = vec![urls(&["a", "b", "c", "d", "e", "f"]), urls(&["g"]), urls(&["a", "h"]), vec![]];
+        let mut rng = StdRng::seed_from_u64(7);
+        let shuffled = TrackerTiers::with_rng(input.clone(), &mut rng);
+        assert_eq!(shuffled.tiers().len(), 3);
+        let mut first = shuffled.tiers()[0].clone();
+        assert_ne!(first, input[0]);
+        first.sort();
+        assert_eq!(first, input[0]);
+        assert_eq!(shuffled.tiers()[1], urls(&["g"]));
+        assert_eq!(shuffled.tiers()[2], urls(&["h"]));
+    }
+
+    #[test]
+    fn test_from_metainfo() {
+        let metainfo = Metainfo::from_bytes(include_bytes!("../../tests/fixtures/multi_file.torrent")).unwrap();
+        let tiers = TrackerTiers::from_metainfo(&metainfo);
+        assert_eq!(tiers.tiers().len(), 2);
+        assert_eq!(tiers.tiers()[1], urls(&["udp://tracker.example.net:6969/announce"]));
+        assert_eq!(tiers.urls().count(), 3);
+
+        let metainfo = Metainfo::from_bytes(include_bytes!("../../tests/fixtures/single_file.torrent")).unwrap();
+        let tiers = TrackerTiers::from_metainfo(&metainfo);
+        assert_eq!(tiers.tiers(), [urls(&["udp://tracker.opentrackr.org:1337/announce"])]);
+    }
+
+    #[test]
+    fn test_promote() {
+        let mut tiers = tiers();
+        tiers.promote("c");
+        tiers.promote("e");
+        tiers.promote("unknown");
+        assert_eq!(tiers.tiers(), [urls(&["c", "a", "b"]), urls(&["e", "d"])]);
+    }
+
+    #[test]
+    fn test_announce_falls_through_tiers() {
+        let mut tiers = tiers();
+        let mut asked = Vec::new();
+        let result = tiers.announce(|url| {
+            asked.push(url.to_string());
+            if url == "d" {
+                Ok(1)
+            } else {
+                Err(url.to_string())
+            }
+        });
+        assert_eq!(result, Some(Ok((String::from("d"), 1))));
+        assert_eq!(asked, urls(&["a", "b", "c", "d"]));
+
+        let result = tiers.announce(|url| if url == "b" { Ok(2) } else { Err(()) });
+        assert_eq!(result, Some(Ok((String::from("b"), 2))));
+        assert_eq!(tiers.tiers(), [urls(&["b", "a", "c"]), urls(&["d", "e"])]);
+
+        let result: Option<Result<(String, ()), &str>> = tiers.announce(|_| Err("down"));
+        assert_eq!(result, Some(Err("down")));
+        let mut empty = TrackerTiers::new(Vec::new());
+        assert!(empty.is_empty());
+        assert_eq!(empty.announce(|_| Ok::<_, ()>(())), None);
+    }
+}
@@ -1,6 +1,6 @@
 use rand::{thread_rng, Rng};
-use crate::str_utils::sub_arr;
-use crate::tracker::utils::{bytes_to_int, int_to_bytes};
+use crate::tracker::utils::int_to_bytes;
+use crate::tracker::TrackerError;
 
 #[derive(Debug)]
 pub struct ConnectionRequest {
@@ -30,29 +30,30 @@ impl ConnectionRequest {
 }
 
 #[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ConnectionRequestAction {
     CONNECT,
     ANNOUNCE,
+    SCRAPE,
+    /// Only ever sent by trackers, with a message explaining what went wrong.
+    ERROR,
 }
 impl ConnectionRequestAction {
-    fn get_code(&self) -> i32 {
+    pub(crate) fn get_code(&self) -> i32 {
         match self {
             ConnectionRequestAction::CONNECT => { 0 }
             ConnectionRequestAction::ANNOUNCE => { 1 }
+            ConnectionRequestAction::SCRAPE => { 2 }
+            ConnectionRequestAction::ERROR => { 3 }
         }
     }
-    fn from_code(code: i32) -> Result<ConnectionRequestAction, ()> {
+    fn from_code(code: i32) -> Option<ConnectionRequestAction> {
         match code {
-            0 => {
-                Ok(ConnectionRequestAction::CONNECT)
-            }
-            1 => {
-                Ok(ConnectionRequestAction::ANNOUNCE)
-            }
-            _ => {
-                println!("Invalid action code {}", code);
-                Err(())
-            }
+            0 => Some(ConnectionRequestAction::CONNECT),
+            1 => Some(ConnectionRequestAction::ANNOUNCE),
+            2 => Some(ConnectionRequestAction::SCRAPE),
+            3 => Some(ConnectionRequestAction::ERROR),
+            _ => None,
         }
     }
 }
@@ -64,7 +65,10 @@ pub struct ConnectionResponse {
     pub connection_id: i64,
 }
 impl ConnectionResponse {
-    pub(crate) fn from_res_bytes(bytes: &[u8]) -> Result<Self, ()> {
+    pub(crate) fn from_res_bytes(bytes: &[u8]) -> Result<Self, TrackerError> {
+        if bytes.len() < 16 {
+            return Err(TrackerError::InvalidResponse);
+        }
         let action_bytes = &bytes[0..4];
         let transaction_id_bytes = &bytes[4..8];
         let connection_id_bytes = &bytes[8..16];
@@ -73,7 +77,7 @@ impl ConnectionResponse {
         let connection_id = i64::from_be_bytes(connection_id_bytes.try_into().unwrap());
         Ok(
             ConnectionResponse {
-                action: action?,
+                action: action.ok_or(TrackerError::InvalidResponse)?,
                 transaction_id,
                 connection_id,
             }
@@ -96,10 +100,12 @@ pub struct AnnounceRequest {
     pub ip_address: i32,
     pub key: i32,
     pub num_want: i32,
-    pub port: i16,
+    pub port: u16,
 }
 impl AnnounceRequest {
-    pub(crate) fn new(connection_id: &i64, info_hash: Vec<u8>) -> Self {
+    /// A request with a random peer ID and key. HTTP trackers ignore
+    /// `connection_id`.
+    pub fn new(connection_id: &i64, info_hash: Vec<u8>) -> Self {
         let mut id = b"-PC0001-".to_vec();
         let mut rng = thread_rng();
         let id_num = rng.gen_range(0..0xFFF);
@@ -144,51 +150,116 @@ impl AnnounceRequest {
 
 #[derive(Debug)]
 pub struct AnnounceResponse {
-    action: ConnectionRequestAction,
-    transaction_id: i32,
-    interval: i32,
-    leechers: i32,
-    seeders: i32,
+    pub action: ConnectionRequestAction,
+    pub transaction_id: i32,
+    pub interval: i32,
+    pub leechers: i32,
+    pub seeders: i32,
     // IP address and TCP port
-    pub(crate) peers: Vec<(String, i16)>,
+    pub peers: Vec<(String, u16)>,
+    /// HTTP only: the tracker asks not to be announced to more often.
+    pub min_interval: Option<i32>,
+    /// HTTP only: to be sent back as `trackerid` on the next announce.
+    pub tracker_id: Option<String>,
+    /// HTTP only: the announce worked, but the tracker has a complaint.
+    pub warning: Option<String>,
 }
 impl AnnounceResponse {
-    pub(crate) fn from_bytes(bytes: &[u8], len: usize) -> Result<Self, ()> {
-        let action_bytes = &bytes[0..4];
-        let action = bytes_to_int(action_bytes);
-        if action == 3 {
-            let message_bytes = sub_arr(bytes.to_vec(), 8, len);
-            println!("Action == 3");
-            println!("Message: {}", String::from_utf8(message_bytes).unwrap());
-            return Err(());
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, TrackerError> {
+        if bytes.len() < 20 {
+            return Err(TrackerError::InvalidResponse);
         }
+        let action_bytes = &bytes[0..4];
         let transaction_id_bytes = &bytes[4..8];
         let interval_bytes = &bytes[8..12];
         let leechers_bytes = &bytes[12..16];
         let seeders_bytes = &bytes[16..20];
-        let mut offset = 20;
-        let mut peers = Vec::new();
-        if offset + 6 <= len {
-            while offset < len {
-                let new_slice = sub_arr(bytes.to_vec(), offset, offset + 6);
-                let ip_bytes = &new_slice[0..4];
-                let port_bytes = &new_slice[4..6];
-                let ip_string = format!("{}.{}.{}.{}", ip_bytes[0], ip_bytes[1], ip_bytes[2], ip_bytes[3]);
-                peers.push((ip_string, bytes_to_int(port_bytes) as i16));
-                offset += 6;
-            }
-        }
-
+        // a trailing partial entry is not a peer
+        let peers = bytes[20..]
+            .chunks_exact(6)
+            .map(|peer| {
+                let ip_string = format!("{}.{}.{}.{}", peer[0], peer[1], peer[2], peer[3]);
+                (ip_string, u16::from_be_bytes([peer[4], peer[5]]))
+            })
+            .collect();
 
         Ok(
             AnnounceResponse {
-                action: ConnectionRequestAction::from_code(i32::from_be_bytes(action_bytes.try_into().unwrap()))?,
+                action: ConnectionRequestAction::from_code(i32::from_be_bytes(action_bytes.try_into().unwrap()))
+                    .ok_or(TrackerError::InvalidResponse)?,
                 transaction_id: i32::from_be_bytes(transaction_id_bytes.try_into().unwrap()),
                 interval: i32::from_be_bytes(interval_bytes.try_into().unwrap()),
                 leechers: i32::from_be_bytes(leechers_bytes.try_into().unwrap()),
                 seeders: i32::from_be_bytes(seeders_bytes.try_into().unwrap()),
                 peers,
+                min_interval: None,
+                tracker_id: None,
+                warning: None,
             }
         )
     }
 }
+
+/// Most info-hashes a UDP tracker takes in one scrape, which keeps the
+/// request within a single unfragmented packet.
+pub const MAX_SCRAPE_HASHES: usize = 74;
+
+pub struct ScrapeRequest {
+    pub connection_id: i64,
+    pub action: ConnectionRequestAction,
+    pub transaction_id: i32,
+    // at most MAX_SCRAPE_HASHES of them
+    pub info_hashes: Vec<[u8; 20]>,
+}
+impl ScrapeRequest {
+    pub(crate) fn new(connection_id: &i64, info_hashes: Vec<[u8; 20]>) -> Self {
+        let transaction_id = thread_rng().gen_range(0..i32::MAX);
+        ScrapeRequest {
+            connection_id: *connection_id,
+            action: ConnectionRequestAction::SCRAPE,
+            transaction_id,
+            info_hashes,
+        }
+    }
+
+    pub(crate) fn to_req_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend(self.connection_id.to_be_bytes());
+        bytes.extend(&self.action.get_code().to_be_bytes());
+        bytes.extend(&self.transaction_id.to_be_bytes());
+        for info_hash in &self.info_hashes {
+            bytes.extend(info_hash);
+        }
+        bytes
+    }
+}
+
+/// Swarm statistics of one torrent, as a scrape reports them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScrapeStats {
+    pub seeders: i32,
+    /// Peers that have ever finished downloading.
+    pub completed: i32,
+    pub leechers: i32,
+    /// HTTP only: the torrent's name, if the tracker knows it.
+    pub name: Option<String>,
+}
+impl ScrapeStats {
+    /// Reads the statistics that follow the 8 byte header of a UDP scrape
+    /// response, one entry per hash in request order.
+    pub(crate) fn from_bytes(bytes: &[u8], count: usize) -> Result<Vec<Self>, TrackerError> {
+        if bytes.len() < 8 + 12 * count {
+            return Err(TrackerError::InvalidResponse);
+        }
+        let field = |entry: &[u8], index: usize| i32::from_be_bytes(entry[index * 4..index * 4 + 4].try_into().unwrap());
+        Ok(bytes[8..8 + 12 * count]
+            .chunks_exact(12)
+            .map(|entry| ScrapeStats {
+                seeders: field(entry, 0),
+                completed: field(entry, 1),
+                leechers: field(entry, 2),
+                name: None,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,511 @@
+use crate::tracker::types::{
+    AnnounceRequest, AnnounceResponse, ConnectionRequest, ConnectionRequestAction, ConnectionResponse, ScrapeRequest,
+    ScrapeStats, MAX_SCRAPE_HASHES,
+};
+use crate::tracker::TrackerError;
+use reqwest::Url;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// BEP 15: wait 15·2ⁿ seconds for the answer to the n-th try.
+pub const RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(15);
+/// BEP 15 retries for n up to and including 8, i.e. 9 tries, the last one
+/// waiting 15·2⁸ = 3840 seconds.
+pub const MAX_TRIES: u32 = 9;
+/// How long a tracker accepts a connection ID after handing it out.
+pub const CONNECTION_ID_LIFETIME: Duration = Duration::from_secs(60);
+
+/// Where a [`UdpTrackerClient`] gets the time to expire connection IDs.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Talks to UDP trackers (BEP 15), retransmitting lost requests and reusing
+/// each tracker's connection ID until it expires.
+#[derive(Debug)]
+pub struct UdpTrackerClient<C: Clock = SystemClock> {
+    socket_v4: UdpSocket,
+    socket_v6: Option<UdpSocket>,
+    clock: C,
+    timeout: Duration,
+    max_tries: u32,
+    // connection IDs by tracker address, with the time they were received
+    connections: HashMap<SocketAddr, (i64, Instant)>,
+}
+
+impl UdpTrackerClient {
+    /// Binds an IPv4 socket and, where the host has IPv6, an IPv6 socket.
+    pub fn bind() -> io::Result<UdpTrackerClient> {
+        let socket_v4 = UdpSocket::bind("0.0.0.0:0")?;
+        let socket_v6 = UdpSocket::bind("[::]:0").ok();
+        Ok(UdpTrackerClient::new(socket_v4, socket_v6))
+    }
+
+    pub fn new(socket_v4: UdpSocket, socket_v6: Option<UdpSocket>) -> UdpTrackerClient {
+        UdpTrackerClient::with_clock(socket_v4, socket_v6, SystemClock)
+    }
+}
+
+impl<C: Clock> UdpTrackerClient<C> {
+    pub fn with_clock(socket_v4: UdpSocket, socket_v6: Option<UdpSocket>, clock: C) -> Self {
+        UdpTrackerClient {
+            socket_v4,
+            socket_v6,
+            clock,
+            timeout: RETRANSMIT_TIMEOUT,
+            max_tries: MAX_TRIES,
+            connections: HashMap::new(),
+        }
+    }
+
+    /// Replaces the 15 second base of the retransmission timeout.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Caps the tries per request, at most 32 since each doubles the wait.
+    pub fn set_max_tries(&mut self, max_tries: u32) {
+        self.max_tries = max_tries.min(32);
+    }
+
+    /// A connection ID for the tracker at `url`: the cached one while it is
+    /// younger than a minute, otherwise a fresh one from a connect request.
+    pub fn connect(&mut self, url: &str) -> Result<i64, TrackerError> {
+        let (addr, _) = resolve(url)?;
+        self.connection_id(addr)
+    }
+
+    /// Announces `info_hash` to the tracker at `url`, connecting first when
+    /// there is no live connection ID.
+    pub fn announce(&mut self, url: &str, info_hash: Vec<u8>) -> Result<AnnounceResponse, TrackerError> {
+        let (addr, url_data) = resolve(url)?;
+        let mut buf = [0; 2048];
+        let len = self.transact_connected(
+            addr,
+            |connection_id| {
+                let mut request = AnnounceRequest::new(&connection_id, info_hash.clone()).to_req_bytes();
+                request.extend(url_data_option(&url_data));
+                request
+            },
+            &mut buf,
+        )?;
+        AnnounceResponse::from_bytes(&buf[..len])
+    }
+
+    /// Asks the tracker at `url` for the swarm statistics of each of
+    /// `info_hashes`, in requests of at most 74 hashes.
+    pub fn scrape(
+        &mut self,
+        url: &str,
+        info_hashes: &[[u8; 20]],
+    ) -> Result<BTreeMap<[u8; 20], ScrapeStats>, TrackerError> {
+        let (addr, _) = resolve(url)?;
+        let mut stats = BTreeMap::new();
+        for chunk in info_hashes.chunks(MAX_SCRAPE_HASHES) {
+            let mut buf = [0; 8 + 12 * MAX_SCRAPE_HASHES];
+            let len = self.transact_connected(
+                addr,
+                |connection_id| ScrapeRequest::new(&connection_id, chunk.to_vec()).to_req_bytes(),
+                &mut buf,
+            )?;
+            let entries = ScrapeStats::from_bytes(&buf[..len], chunk.len())?;
+            stats.extend(chunk.iter().copied().zip(entries));
+        }
+        Ok(stats)
+    }
+
+    fn connection_id(&mut self, addr: SocketAddr) -> Result<i64, TrackerError> {
+        let now = self.clock.now();
+        if let Some(&(connection_id, received)) = self.connections.get(&addr) {
+            if now.duration_since(received) < CONNECTION_ID_LIFETIME {
+                return Ok(connection_id);
+            }
+        }
+        let request = ConnectionRequest::new(ConnectionRequestAction::CONNECT).to_req_bytes();
+        let mut buf = [0; 2048];
+        let mut answer = None;
+        for attempt in 0..self.max_tries {
+            answer = self.try_once(addr, &request, attempt, &mut buf)?;
+            if answer.is_some() {
+                break;
+            }
+        }
+        let len = answer.ok_or(TrackerError::TimedOut { tries: self.max_tries })?;
+        let response = ConnectionResponse::from_res_bytes(&buf[..len])?;
+        // the lifetime starts when the ID arrives, not when it was asked for
+        self.connections.insert(addr, (response.connection_id, self.clock.now()));
+        Ok(response.connection_id)
+    }
+
+    // Sends the request `build` makes from a connection ID until the answer
+    // arrives or every try has timed out, and returns the answer's length.
+    // Retransmits go on far longer than a connection ID lives, so an expired
+    // ID is replaced and the request rebuilt before the next try. A tracker
+    // error may mean it no longer knows the ID, which is then forgotten.
+    fn transact_connected(
+        &mut self,
+        addr: SocketAddr,
+        build: impl Fn(i64) -> Vec<u8>,
+        buf: &mut [u8],
+    ) -> Result<usize, TrackerError> {
+        let mut current: Option<(i64, Vec<u8>)> = None;
+        for attempt in 0..self.max_tries {
+            let connection_id = self.connection_id(addr)?;
+            let request = match current.take() {
+                Some((id, request)) if id == connection_id => request,
+                _ => build(connection_id),
+            };
+            match self.try_once(addr, &request, attempt, buf) {
+                Ok(Some(len)) => return Ok(len),
+                Ok(None) => current = Some((connection_id, request)),
+                Err(e) => {
+                    if matches!(e, TrackerError::Tracker(_)) {
+                        self.connections.remove(&addr);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Err(TrackerError::TimedOut { tries: self.max_tries })
+    }
+
+    // Sends `request` to `addr` once and waits out the timeout of try
+    // `attempt` for the answer, returning its length, or None if it never
+    // came. Datagrams from elsewhere or for other transactions are skipped, so
+    // several requests can share a socket and late answers to earlier
+    // requests do no harm.
+    fn try_once(
+        &self,
+        addr: SocketAddr,
+        request: &[u8],
+        attempt: u32,
+        buf: &mut [u8],
+    ) -> Result<Option<usize>, TrackerError> {
+        let socket = match (addr, &self.socket_v6) {
+            (SocketAddr::V4(_), _) => &self.socket_v4,
+            (SocketAddr::V6(_), Some(socket_v6)) => socket_v6,
+            (SocketAddr::V6(_), None) => return Err(TrackerError::Unreachable(addr.to_string())),
+        };
+        // every request has an 8 byte connection or protocol ID, then the
+        // action and the transaction ID
+        let (action, transaction_id) = (&request[8..12], &request[12..16]);
+        socket.send_to(request, addr)?;
+        let deadline = self.clock.now() + retransmit_timeout(self.timeout, attempt);
+        loop {
+            let remaining = deadline.saturating_duration_since(self.clock.now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            socket.set_read_timeout(Some(remaining))?;
+            let (len, from) = match socket.recv_from(buf) {
+                Ok(received) => received,
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            if from != addr || len < 8 || &buf[4..8] != transaction_id {
+                continue;
+            }
+            if buf[..4] == ConnectionRequestAction::ERROR.get_code().to_be_bytes() {
+                return Err(TrackerError::Tracker(String::from_utf8_lossy(&buf[8..len]).into_owned()));
+            }
+            if &buf[..4] != action {
+                return Err(TrackerError::InvalidResponse);
+            }
+            return Ok(Some(len));
+        }
+    }
+}
+
+fn retransmit_timeout(base: Duration, attempt: u32) -> Duration {
+    base.saturating_mul(2u32.saturating_pow(attempt))
+}
+
+// The tracker's address and the path and query of its url, which BEP 41
+// passes along with announces.
+fn resolve(url: &str) -> Result<(SocketAddr, String), TrackerError> {
+    let parsed = Url::parse(url).map_err(|_| TrackerError::InvalidUrl(url.to_string()))?;
+    if parsed.scheme() != "udp" {
+        return Err(TrackerError::UnsupportedScheme(parsed.scheme().to_string()));
+    }
+    let (Some(host), Some(port)) = (parsed.host_str(), parsed.port()) else {
+        return Err(TrackerError::InvalidUrl(url.to_string()));
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addr = (host, port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| TrackerError::Unreachable(host.to_string()))?;
+    let mut url_data = parsed.path().to_string();
+    if let Some(query) = parsed.query() {
+        url_data.push('?');
+        url_data.push_str(query);
+    }
+    Ok((addr, url_data))
+}
+
+// BEP 41 URLData options of at most 255 bytes each, then EndOfOptions.
+fn url_data_option(url_data: &str) -> Vec<u8> {
+    let mut option = Vec::new();
+    if url_data.is_empty() || url_data == "/" {
+        return option;
+    }
+    for chunk in url_data.as_bytes().chunks(255) {
+        option.push(0x2);
+        option.push(chunk.len() as u8);
+        option.extend(chunk);
+    }
+    option.push(0x0);
+    option
+}
+
+#[cfg(test)]
+mod udp_tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    // Shared with stand-in trackers, so they can let time pass while the
+    // client waits for them.
+    #[derive(Clone)]
+    struct SimulatedClock(Arc<Mutex<Instant>>);
+
+    impl SimulatedClock {
+        fn advance(&self, by: Duration) {
+            *self.0.lock().unwrap() += by;
+        }
+    }
+
+    impl Clock for SimulatedClock {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    // Actions of the requests a stand-in tracker has seen, lost ones included.
+    type Seen = Arc<Mutex<Vec<i32>>>;
+
+    // A tracker on a local port that ignores its first `drop` requests,
+    // hands out connection IDs 1, 2, ... and only accepts the latest one.
+    fn stand_in(drop: usize) -> (String, Seen) {
+        stand_in_with(drop, |socket, to, response| {
+            socket.send_to(&response, to).unwrap();
+        })
+    }
+
+    // Like `stand_in`, with `reply` sending each response.
+    fn stand_in_with(drop: usize, reply: impl Fn(&UdpSocket, SocketAddr, Vec<u8>) + Send + 'static) -> (String, Seen) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let url = format!("udp://{}/announce", socket.local_addr().unwrap());
+        let seen = Seen::default();
+        let log = seen.clone();
+        thread::spawn(move || {
+            let mut connection_id = 0i64;
+            let mut buf = [0; 2048];
+            while let Ok((len, from)) = socket.recv_from(&mut buf) {
+                let action = i32::from_be_bytes(buf[8..12].try_into().unwrap());
+                log.lock().unwrap().push(action);
+                if log.lock().unwrap().len() <= drop {
+                    continue;
+                }
+                let mut response = buf[8..16].to_vec();
+                if action == 0 {
+                    connection_id += 1;
+                    response.extend(connection_id.to_be_bytes());
+                } else if i64::from_be_bytes(buf[..8].try_into().unwrap()) != connection_id || len < 36 {
+                    response[..4].copy_from_slice(&3i32.to_be_bytes());
+                    response.extend(b"bad connection id");
+                } else if action == 2 {
+                    // the first byte of each hash as seeders, ten times that completed
+                    for info_hash in buf[16..len].chunks(20) {
+                        for value in [info_hash[0] as i32, info_hash[0] as i32 * 10, 1] {
+                            response.extend(value.to_be_bytes());
+                        }
+                    }
+                } else {
+                    // interval, leechers, seeders and one peer
+                    for value in [1800i32, 2, 5] {
+                        response.extend(value.to_be_bytes());
+                    }
+                    response.extend([10, 0, 0, 1, 0x1a, 0xe1]);
+                }
+                reply(&socket, from, response);
+            }
+        });
+        (url, seen)
+    }
+
+    fn client() -> (UdpTrackerClient<SimulatedClock>, SimulatedClock) {
+        let clock = SimulatedClock(Arc::new(Mutex::new(Instant::now())));
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client = UdpTrackerClient::with_clock(socket, None, clock.clone());
+        client.set_timeout(Duration::from_millis(10));
+        (client, clock)
+    }
+
+    #[test]
+    fn test_retransmit_timeouts() {
+        let timeouts: Vec<u64> = (0..MAX_TRIES)
+            .map(|attempt| retransmit_timeout(RETRANSMIT_TIMEOUT, attempt).as_secs())
+            .collect();
+        assert_eq!(timeouts, [15, 30, 60, 120, 240, 480, 960, 1920, 3840]);
+        assert_eq!(retransmit_timeout(RETRANSMIT_TIMEOUT, 31).as_secs(), 15 << 31);
+        assert_eq!(retransmit_timeout(RETRANSMIT_TIMEOUT, 40), RETRANSMIT_TIMEOUT * u32::MAX);
+    }
+
+    #[test]
+    fn test_retransmits_lost_requests() {
+        let (url, seen) = stand_in(2);
+        let (mut client, _) = client();
+        let response = client.announce(&url, vec![7; 20]).unwrap();
+        assert_eq!(response.interval, 1800);
+        assert_eq!(response.seeders, 5);
+        assert_eq!(response.peers, [(String::from("10.0.0.1"), 6881)]);
+        assert_eq!(*seen.lock().unwrap(), [0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_gives_up() {
+        let (url, seen) = stand_in(usize::MAX);
+        let (mut client, _) = client();
+        client.set_timeout(Duration::from_millis(1));
+        assert!(matches!(client.connect(&url), Err(TrackerError::TimedOut { tries: 9 })));
+        assert_eq!(seen.lock().unwrap().len(), 9);
+    }
+
+    #[test]
+    fn test_connection_id_expires() {
+        let (url, seen) = stand_in(0);
+        let (mut client, clock) = client();
+        client.announce(&url, vec![7; 20]).unwrap();
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(client.connect(&url).unwrap(), 1);
+        client.announce(&url, vec![7; 20]).unwrap();
+        clock.advance(Duration::from_secs(1));
+        client.announce(&url, vec![7; 20]).unwrap();
+        assert_eq!(client.connect(&url).unwrap(), 2);
+        assert_eq!(*seen.lock().unwrap(), [0, 1, 1, 0, 1]);
+    }
+
+    #[test]
+    fn test_reconnects_between_retransmits() {
+        let (mut client, clock) = client();
+        let tracker_clock = clock.clone();
+        let lost = AtomicBool::new(false);
+        let (url, seen) = stand_in_with(0, move |socket, to, response| {
+            // the first announce answer is lost while the connection ID expires
+            if response[..4] == 1i32.to_be_bytes() && !lost.swap(true, Ordering::SeqCst) {
+                tracker_clock.advance(CONNECTION_ID_LIFETIME);
+                return;
+            }
+            socket.send_to(&response, to).unwrap();
+        });
+        // the tracker rejects the stale ID, so the retransmit must carry a new one
+        assert_eq!(client.announce(&url, vec![7; 20]).unwrap().seeders, 5);
+        assert_eq!(*seen.lock().unwrap(), [0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn test_error_forgets_connection_id() {
+        let rejected = AtomicBool::new(false);
+        let (url, seen) = stand_in_with(0, move |socket, to, mut response| {
+            if response[..4] == 1i32.to_be_bytes() && !rejected.swap(true, Ordering::SeqCst) {
+                response.truncate(8);
+                response[..4].copy_from_slice(&3i32.to_be_bytes());
+                response.extend(b"bad connection id");
+            }
+            socket.send_to(&response, to).unwrap();
+        });
+        let (mut client, _) = client();
+        assert!(matches!(client.announce(&url, vec![7; 20]), Err(TrackerError::Tracker(_))));
+        assert_eq!(client.announce(&url, vec![7; 20]).unwrap().seeders, 5);
+        assert_eq!(*seen.lock().unwrap(), [0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn test_skips_unrelated_datagrams() {
+        let (url, _) = stand_in_with(0, |socket, to, response| {
+            // the right answer from the wrong address
+            let mut forged = response.clone();
+            forged[8..16].copy_from_slice(&99i64.to_be_bytes());
+            UdpSocket::bind("127.0.0.1:0").unwrap().send_to(&forged, to).unwrap();
+            // another transaction's answer
+            forged[4] ^= 0xff;
+            socket.send_to(&forged, to).unwrap();
+            socket.send_to(&forged[..7], to).unwrap();
+            socket.send_to(&response, to).unwrap();
+        });
+        let (mut client, _) = client();
+        client.set_timeout(Duration::from_secs(5));
+        assert_eq!(client.connect(&url).unwrap(), 1);
+        assert_eq!(client.announce(&url, vec![7; 20]).unwrap().peers.len(), 1);
+    }
+
+    #[test]
+    fn test_rejects_errors_and_short_responses() {
+        let (url, _) = stand_in_with(0, |socket, to, mut response| {
+            if response[..4] == 1i32.to_be_bytes() {
+                response.truncate(8);
+                response[..4].copy_from_slice(&3i32.to_be_bytes());
+                response.extend(b"unregistered torrent");
+            }
+            socket.send_to(&response, to).unwrap();
+        });
+        let (mut client, _) = client();
+        match client.announce(&url, vec![7; 20]) {
+            Err(TrackerError::Tracker(message)) => assert_eq!(message, "unregistered torrent"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let (url, _) = stand_in_with(0, |socket, to, response| {
+            socket.send_to(&response[..12], to).unwrap();
+        });
+        assert!(matches!(client.connect(&url), Err(TrackerError::InvalidResponse)));
+    }
+
+    #[test]
+    fn test_scrape() {
+        let (url, seen) = stand_in(0);
+        let (mut client, _) = client();
+        let info_hashes: Vec<[u8; 20]> = (0..80).map(|i| [i; 20]).collect();
+        let stats = client.scrape(&url, &info_hashes).unwrap();
+        assert_eq!(stats.len(), 80);
+        assert_eq!(
+            stats[&[79; 20]],
+            ScrapeStats {
+                seeders: 79,
+                completed: 790,
+                leechers: 1,
+                name: None
+            }
+        );
+        // 74 hashes, then the remaining 6 over the same connection
+        assert_eq!(*seen.lock().unwrap(), [0, 2, 2]);
+    }
+
+    #[test]
+    fn test_url_data() {
+        let (addr, url_data) = resolve("udp://127.0.0.1:6969/announce?key=abc").unwrap();
+        assert_eq!(addr, "127.0.0.1:6969".parse().unwrap());
+        assert_eq!(url_data_option(&url_data), b"\x02\x11/announce?key=abc\x00");
+        assert!(url_data_option("/").is_empty());
+        let long = format!("/{}", "a".repeat(299));
+        let option = url_data_option(&long);
+        assert_eq!((option[1], option[257], option[258], option.len()), (255, 2, 45, 305));
+        assert!(matches!(resolve("http://t.example/announce"), Err(TrackerError::UnsupportedScheme(_))));
+        assert!(matches!(resolve("udp://t.example/announce"), Err(TrackerError::InvalidUrl(_))));
+    }
+}